@@ -0,0 +1,36 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use femark::{render_markdown, Renderer};
+
+const CODE_HEAVY: &str = include_str!("fixtures/code_heavy.md");
+const PROSE: &str = include_str!("fixtures/prose.md");
+
+fn render(c: &mut Criterion) {
+  let mut group = c.benchmark_group("render");
+  for (name, post) in [("code_heavy", CODE_HEAVY), ("prose", PROSE)] {
+    group.throughput(Throughput::Bytes(post.len() as u64));
+    group.bench_function(name, |b| {
+      b.iter(|| render_markdown(black_box(post), Default::default()).unwrap())
+    });
+  }
+  group.finish();
+}
+
+fn batch(c: &mut Criterion) {
+  let posts = [CODE_HEAVY, PROSE]
+    .iter()
+    .cycle()
+    .take(64)
+    .map(|post| post.to_string())
+    .collect::<Vec<_>>();
+
+  let renderer = Renderer::from_options(Default::default()).unwrap();
+  let mut group = c.benchmark_group("batch");
+  group.throughput(Throughput::Elements(posts.len() as u64));
+  group.bench_function("64_posts", |b| {
+    b.iter(|| renderer.render_batch(black_box(&posts)))
+  });
+  group.finish();
+}
+
+criterion_group!(benches, render, batch);
+criterion_main!(benches);
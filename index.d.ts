@@ -6,11 +6,326 @@
 export interface HtmlOutput {
   toc?: string
   content: string
+  /** Problems that didn't stop the document from rendering */
+  warnings: Array<Warning>
+  /**
+   * Link and image targets that neither the link rules nor the `resolveLink` hook could
+   * resolve, in the order they appear. They are rendered as written
+   */
+  unresolvedLinks: Array<string>
+  /** The headings, links, images, footnotes and code blocks of the document */
+  outline: Outline
+  /** Words of prose, leaving out code blocks and image descriptions */
+  wordCount: number
+  /** Minutes it takes to read the prose, rounded up */
+  readingTimeMinutes: number
+  excerpt: Excerpt
+  /** The document split at its headings into plain text, when `searchIndex` is set */
+  sections?: Array<SearchSection>
+}
+/** A stretch of a document between two headings, as plain text for a search index */
+export interface SearchSection {
+  /**
+   * The anchor of the heading, for deep links to the section. Empty for the text before
+   * the first heading
+   */
+  slug: string
+  /** The heading's plain text */
+  title: string
+  /** 1 through 6, or 0 for the text before the first heading */
+  level: number
+  text: string
+}
+/** The start of a document, for post listings and link previews */
+export interface Excerpt {
+  /** The first words of the prose, or all of it up to a `<!-- more -->` marker */
+  text: string
+  /**
+   * The rendered blocks up to the marker, or up to the block that reaches the word limit.
+   * Stops short of a block that would take it past 16 KiB
+   */
+  html: string
+}
+/** Everything the renderer saw in a document, for tooling like link checkers and sitemaps */
+export interface Outline {
+  /** The headings that made it into the table of contents */
+  headings: Array<OutlineHeading>
+  /** Every link, after the link rules ran */
+  links: Array<OutlineLink>
+  images: Array<OutlineImage>
+  /** Footnotes by label, in the order they were first referenced or defined */
+  footnotes: Array<OutlineFootnote>
+  codeBlocks: Array<OutlineCodeBlock>
+}
+export interface OutlineHeading {
+  level: number
+  slug: string
+  /** The heading's plain text */
+  text: string
+}
+export interface OutlineLink {
+  url: string
+  /** The link's plain text */
+  text: string
+}
+export interface OutlineImage {
+  src: string
+  alt: string
+  title?: string
+}
+export interface OutlineFootnote {
+  label: string
+  /** How many times the footnote is referenced */
+  references: number
+  /** Whether the document defines it, so a link checker can flag dangling references */
+  defined: boolean
+}
+export interface OutlineCodeBlock {
+  /**
+   * The language the block was rendered as, after detection and defaults. Empty when it
+   * has none
+   */
+  lang: string
+  lines: number
+}
+/**
+ * Something that didn't stop a document from rendering but likely needs attention, like
+ * a code block in an unknown language
+ */
+export interface Warning {
+  /** A stable name for the kind of problem, e.g. "UNKNOWN_LANGUAGE" */
+  code: string
+  message: string
+}
+export interface MarkdownOptions {
+  /** Which markdown extensions to enable */
+  extensions?: MarkdownExtensions
+  /**
+   * Guess the language of indented code blocks and fences without an info string,
+   * and highlight them as if they had been labeled. Off by default
+   */
+  detectLanguage?: boolean
+  /**
+   * Language used for indented code blocks and fences without an info string, e.g. "rust".
+   * Detection, when enabled, takes precedence
+   */
+  defaultLanguage?: string
+  /** Reuse the markup of code blocks that were already highlighted */
+  highlightCache?: HighlightCacheOptions
+  /**
+   * Code blocks longer than this many bytes are escaped without being highlighted or
+   * detected. Unlimited by default
+   */
+  maxHighlightBytes?: number
+  /**
+   * Milliseconds a single code block may spend being highlighted before it falls back to
+   * escaped text. Unlimited by default
+   */
+  highlightTimeoutMs?: number
+  /**
+   * What to do with HTML written in the markdown: "allow" it (the default), "escape" it so
+   * it shows up as text, or "strip" it. Escaping or stripping also replaces `javascript:`
+   * and other dangerous link and image URLs, for rendering markdown that can't be trusted
+   */
+  rawHtml?: 'allow' | 'escape' | 'strip'
+  /** Words in the excerpt, 50 by default. A `<!-- more -->` marker ends it earlier */
+  excerptWords?: number
+  /** Reading speed for the reading time, 200 words per minute by default */
+  wordsPerMinute?: number
+  /** Also return the document as plain text sections, one per heading, for search indexes */
+  searchIndex?: SearchIndexOptions
+  /** Extra attributes for images, and `<figure>` wrappers for titled ones */
+  images?: ImageOptions
+  /**
+   * Rules for rewriting links between documents, e.g. `../guide/intro.md#setup` to
+   * `/guide/intro#setup`
+   */
+  links?: LinkOptions
+  /** Open links to other sites in a new tab, with `rel="noopener noreferrer nofollow"` */
+  externalLinks?: ExternalLinkOptions
+  /**
+   * Render GitHub's `> [!NOTE]` blockquotes and `:::tip` containers as
+   * `<aside class="callout callout-note">`. Off by default
+   */
+  callouts?: boolean
+  /**
+   * Render `$...$`, `$$...$$` and ```` ```math ```` formulas as `math-inline` and
+   * `math-display` elements for KaTeX or MathJax to typeset. Off by default
+   */
+  math?: boolean
+  /**
+   * Languages of code blocks that hold diagrams, like "mermaid", "dot" or "plantuml". Their
+   * source is emitted untouched in a container for a client side renderer, without the
+   * code block markup
+   */
+  diagrams?: Array<string>
+}
+/** How images are rendered. Every option is off unless turned on */
+export interface ImageOptions {
+  /** Add `loading="lazy"`, so offscreen images load as they are scrolled to */
+  lazy?: boolean
+  /** Add `decoding="async"` */
+  asyncDecoding?: boolean
+  /**
+   * Render a titled image that is alone in its paragraph as a `<figure>`, with the title
+   * as its `<figcaption>`
+   */
+  figures?: boolean
+  /**
+   * Dimensions and sources of images by their src, after the link rules ran. The
+   * `imageInfo` hook takes precedence
+   */
+  manifest?: Record<string, ImageInfo>
+}
+/**
+ * What is known about an image file. Setting the dimensions lets the browser reserve space
+ * for the image before it loads
+ */
+export interface ImageInfo {
+  width?: number
+  height?: number
+  srcset?: string
+  sizes?: string
+}
+/** What goes into the search sections */
+export interface SearchIndexOptions {
+  /** Include the text of code blocks. Off by default */
+  includeCode?: boolean
+}
+/** Which links count as external and how they are marked */
+export interface ExternalLinkOptions {
+  /**
+   * The site's own origin, e.g. "https://example.com". Links to its host stay untouched,
+   * links to any other http(s) host are external. Without it every http(s) link is treated
+   * as external
+   */
+  siteOrigin?: string
+  /** Class added to external links, e.g. to show an icon beside them */
+  class?: string
+}
+/**
+ * How relative links and images are rewritten. URLs with a scheme, like `https:`, and
+ * fragments such as `#setup` are left alone
+ */
+export interface LinkOptions {
+  /** Prepended to every root relative link once the other rules ran, e.g. "/docs" */
+  baseUrl?: string
+  /**
+   * Site path of the document being rendered, e.g. "/tutorial/start". Relative links are
+   * resolved against it, and ones that climb above the root are reported as unresolved
+   */
+  path?: string
+  /** Drop the `.md` or `.markdown` extension from link paths. Off by default */
+  stripMdExtension?: boolean
+  /**
+   * Path prefixes to replace, e.g. {"/api/": "https://docs.rs/femark/latest/femark/"}.
+   * The longest matching prefix wins
+   */
+  rewrites?: Record<string, string>
+}
+/**
+ * Keeps highlighted code blocks keyed by language and source, so repeated snippets are
+ * only highlighted once. Renderers with the same options share one cache for the life of
+ * the process
+ */
+export interface HighlightCacheOptions {
+  /** Number of blocks kept in memory, 1024 by default */
+  capacity?: number
+  /** Directory the cache is persisted to, so later runs can reuse it */
+  directory?: string
+}
+/**
+ * Markdown syntax extensions. Tables, footnotes, strikethrough and task lists are on
+ * unless turned off, smart punctuation and heading attributes are off unless turned on
+ */
+export interface MarkdownExtensions {
+  tables?: boolean
+  footnotes?: boolean
+  strikethrough?: boolean
+  tasklists?: boolean
+  smartPunctuation?: boolean
+  /** `{#id .class}` after a heading sets its anchor and classes */
+  headingAttributes?: boolean
+}
+export interface RendererConfig {
+  options?: MarkdownOptions
+  /** Name of a built in theme for the stylesheet: "basic", "github-light" or "github-dark" */
+  theme?: string
+  /** Theme used when the reader prefers a dark color scheme */
+  darkTheme?: string
+  /** Extra names for registered languages, e.g. {"rs": "rust"} */
+  languages?: Record<string, string>
+}
+/**
+ * JavaScript callbacks that customize rendering. Returning undefined from a hook keeps
+ * femark's default behavior
+ */
+export interface RendererHooks {
+  /** (text: string) => string, builds the anchor slug of a heading */
+  slugify?: (text: string) => string | undefined | null
+  /**
+   * (lang: string, source: string) => string, highlights code in languages femark has no
+   * grammar for. The returned HTML is not escaped
+   */
+  highlight?: (lang: string, source: string) => string | undefined | null
+  /**
+   * (url: string) => string | false, rewrites the target of a link or image. Returning
+   * false reports the target as unresolved, undefined leaves it to the link rules
+   */
+  resolveLink?: (url: string) => string | false | undefined | null
+  /**
+   * (src: string) => ImageInfo, supplies the width, height and srcset of an image. Only
+   * called when the images option is set
+   */
+  imageInfo?: (src: string) => ImageInfo | undefined | null
 }
 /**
  * Processes markdown to html and syntax highlights the code blocks
  * Takes in a string and returns an object containing the content HTML and the toc html
- * Input: string
+ * Input: string, optional options object
  * Output: {toc: string, content: string}
  */
-export function processMarkdownToHtml(input: string): HtmlOutput
+export function processMarkdownToHtml(input: string, options?: MarkdownOptions | undefined | null): HtmlOutput
+/**
+ * Processes markdown to html like processMarkdownToHtml, but on a worker thread
+ * Input: string, optional options object
+ * Output: Promise<{toc: string, content: string}>
+ */
+export function processMarkdownToHtmlAsync(input: string, options?: MarkdownOptions | undefined | null): Promise<HtmlOutput>
+/**
+ * Processes markdown to html, handing the HTML to onChunk piece by piece as it is rendered
+ * instead of returning it all at once. Chunks arrive in order and are roughly 16KiB each
+ * Input: string, (chunk: string) => void, optional options object
+ * Output: {toc: string, content: ""}, returned once the last chunk was emitted
+ */
+export function processMarkdownToHtmlStream(input: string, onChunk: (chunk: string) => void, options?: MarkdownOptions | undefined | null): HtmlOutput
+/** The outcome of rendering one document of a batch. Exactly one of output and error is set */
+export interface BatchResult {
+  output?: HtmlOutput
+  error?: string
+  /** The code of the error, e.g. "PANICKED" */
+  code?: string
+}
+/**
+ * Processes many markdown documents in parallel on a pool of worker threads
+ * A document that fails to render reports its error in its own result, the rest of
+ * the batch is unaffected
+ * Input: string[], optional options object shared by every document
+ * Output: {output?: {toc: string, content: string}, error?: string}[], in input order
+ */
+export function processMarkdownBatch(inputs: Array<string>, options?: MarkdownOptions | undefined | null): Array<BatchResult>
+/**
+ * Renders markdown with a configuration that is validated once, up front
+ * Construct it with the options, themes, language aliases and hooks to use, then call
+ * render for every document
+ */
+export class MarkdownRenderer {
+  constructor(config?: RendererConfig | undefined | null, hooks?: RendererHooks | undefined | null)
+  /**
+   * Processes markdown to html with this renderer's configuration
+   * Output: {toc: string, content: string}
+   */
+  render(input: string): HtmlOutput
+  /** CSS for the configured theme and dark theme, if any were set */
+  stylesheet(): string | null
+}
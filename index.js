@@ -236,6 +236,16 @@ if (!nativeBinding) {
   throw new Error(`Failed to load native binding`)
 }
 
-const { processMarkdownToHtml } = nativeBinding
+const {
+  processMarkdownToHtml,
+  processMarkdownToHtmlAsync,
+  processMarkdownToHtmlStream,
+  processMarkdownBatch,
+  MarkdownRenderer,
+} = nativeBinding
 
 module.exports.processMarkdownToHtml = processMarkdownToHtml
+module.exports.processMarkdownToHtmlAsync = processMarkdownToHtmlAsync
+module.exports.processMarkdownToHtmlStream = processMarkdownToHtmlStream
+module.exports.processMarkdownBatch = processMarkdownBatch
+module.exports.MarkdownRenderer = MarkdownRenderer
@@ -0,0 +1,339 @@
+//! Renders markdown files, or stdin, to HTML from the command line
+
+// The Node bindings look for Node-API when the binary starts and complain on stderr for
+// every function they can't find
+#[cfg(feature = "node")]
+compile_error!("build the CLI without the Node bindings: --no-default-features --features cli");
+
+use clap::Parser;
+use eyre::{bail, eyre, Result, WrapErr};
+use femark::{
+  ExternalLinkOptions, ImageOptions, LinkOptions, MarkdownExtensions, MarkdownOptions, Renderer,
+  RendererConfig,
+};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Parser)]
+#[command(
+  name = "femark",
+  version,
+  about = "Render markdown to HTML with syntax highlighting"
+)]
+struct Args {
+  /// Markdown files or glob patterns such as "docs/**/*.md". Reads stdin when none are given
+  /// or when one of them is "-"
+  inputs: Vec<String>,
+  /// Write each rendered file to this directory, keeping its path relative to the glob,
+  /// instead of printing everything to stdout
+  #[arg(short, long)]
+  out_dir: Option<PathBuf>,
+  /// Put the table of contents in front of the content
+  #[arg(long)]
+  toc: bool,
+  /// Theme for --emit-css: basic, github-light or github-dark
+  #[arg(long)]
+  theme: Option<String>,
+  /// Theme used when the reader prefers a dark color scheme
+  #[arg(long)]
+  dark_theme: Option<String>,
+  /// Print the stylesheet for the chosen themes and exit
+  #[arg(long)]
+  emit_css: bool,
+  /// Guess the language of code blocks that don't name one
+  #[arg(long)]
+  detect_language: bool,
+  /// Language for code blocks that don't name one
+  #[arg(long)]
+  default_language: Option<String>,
+  /// Escape code blocks longer than this many bytes instead of highlighting them
+  #[arg(long, value_name = "BYTES")]
+  max_highlight_bytes: Option<u32>,
+  /// Escape code blocks that take longer than this to highlight
+  #[arg(long, value_name = "MS")]
+  highlight_timeout_ms: Option<u32>,
+  /// What to do with HTML in the markdown. Escaping or stripping it also filters dangerous
+  /// link and image URLs, for markdown that can't be trusted
+  #[arg(long, value_parser = ["allow", "escape", "strip"])]
+  raw_html: Option<String>,
+  /// Prepended to root relative links, e.g. /docs
+  #[arg(long, value_name = "URL")]
+  base_url: Option<String>,
+  /// Drop the .md extension from links, so links between markdown files point at the
+  /// rendered pages
+  #[arg(long)]
+  strip_md_extension: bool,
+  /// Replace a link path prefix, like /api/=https://docs.rs/femark/. Can be repeated
+  #[arg(long = "link-rewrite", value_name = "PREFIX=REPLACEMENT")]
+  link_rewrites: Vec<String>,
+  /// Open links to other sites in a new tab, with rel="noopener noreferrer nofollow". Links
+  /// to the given origin, like --external-links=https://example.com, are left alone
+  #[arg(long, value_name = "ORIGIN", num_args = 0..=1, require_equals = true)]
+  external_links: Option<Option<String>>,
+  /// Class added to external links
+  #[arg(long, value_name = "CLASS", requires = "external_links")]
+  external_link_class: Option<String>,
+  /// Add loading="lazy" and decoding="async" to images
+  #[arg(long)]
+  lazy_images: bool,
+  /// Render titled images that are alone in a paragraph as figures with a caption
+  #[arg(long)]
+  figures: bool,
+  /// Render > [!NOTE] blockquotes and :::tip containers as callouts
+  #[arg(long)]
+  callouts: bool,
+  /// Render $...$, $$...$$ and math fences as elements for KaTeX or MathJax
+  #[arg(long)]
+  math: bool,
+  /// Emit code blocks in this language as a diagram container for a client side renderer,
+  /// like mermaid. Can be repeated
+  #[arg(long = "diagram", value_name = "LANG")]
+  diagrams: Vec<String>,
+  /// Extra name for a supported language, like rs=rust. Can be repeated
+  #[arg(long = "lang-alias", value_name = "ALIAS=LANG")]
+  lang_aliases: Vec<String>,
+  /// Turn off GitHub style tables
+  #[arg(long)]
+  no_tables: bool,
+  /// Turn off footnotes
+  #[arg(long)]
+  no_footnotes: bool,
+  /// Turn off ~~strikethrough~~
+  #[arg(long)]
+  no_strikethrough: bool,
+  /// Turn off - [ ] task lists
+  #[arg(long)]
+  no_tasklists: bool,
+  /// Turn straight quotes and dashes into typographic ones
+  #[arg(long)]
+  smart_punctuation: bool,
+  /// Allow {#id .class} attributes on headings
+  #[arg(long)]
+  heading_attributes: bool,
+}
+
+/// A document to render, and where its HTML goes when writing to a directory
+struct Document {
+  source: Option<PathBuf>,
+  relative: PathBuf,
+}
+
+fn main() -> Result<()> {
+  let args = Args::parse();
+  let renderer = Renderer::new(config(&args)?)?;
+
+  if args.emit_css {
+    let css = match renderer.stylesheet() {
+      Some(css) => css,
+      // No theme picked, fall back to the basic one
+      None => Renderer::new(RendererConfig {
+        theme: Some("basic".into()),
+        ..Default::default()
+      })?
+      .stylesheet()
+      .unwrap_or_default(),
+    };
+    io::stdout().write_all(css.as_bytes())?;
+    return Ok(());
+  }
+
+  let documents = documents(&args.inputs)?;
+  let inputs = documents
+    .iter()
+    .map(|document| match &document.source {
+      Some(path) => {
+        fs::read_to_string(path).wrap_err_with(|| format!("could not read {}", path.display()))
+      }
+      None => {
+        let mut input = String::new();
+        io::stdin().read_to_string(&mut input)?;
+        Ok(input)
+      }
+    })
+    .collect::<Result<Vec<_>>>()?;
+
+  let mut stdout = io::stdout().lock();
+  for (document, result) in documents.iter().zip(renderer.render_batch(&inputs)) {
+    let name = document
+      .source
+      .as_ref()
+      .map_or_else(|| "stdin".into(), |path| path.display().to_string());
+    let output = result.wrap_err_with(|| format!("could not render {name}"))?;
+    for warning in &output.warnings {
+      eprintln!("warning: {name}: {}", warning.message);
+    }
+    for link in &output.unresolved_links {
+      eprintln!("warning: {name}: unresolved link {link:?}");
+    }
+
+    let mut html = String::new();
+    if args.toc {
+      html.push_str(output.toc.as_deref().unwrap_or_default());
+    }
+    html.push_str(&output.content);
+
+    match &args.out_dir {
+      Some(out_dir) => {
+        let path = out_dir.join(&document.relative).with_extension("html");
+        if let Some(parent) = path.parent() {
+          fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, html).wrap_err_with(|| format!("could not write {}", path.display()))?;
+      }
+      None => stdout.write_all(html.as_bytes())?,
+    }
+  }
+
+  Ok(())
+}
+
+fn config(args: &Args) -> Result<RendererConfig> {
+  let languages = pairs(&args.lang_aliases, "--lang-alias expects ALIAS=LANG")?;
+  let rewrites = pairs(
+    &args.link_rewrites,
+    "--link-rewrite expects PREFIX=REPLACEMENT",
+  )?;
+
+  Ok(RendererConfig {
+    options: Some(MarkdownOptions {
+      extensions: Some(MarkdownExtensions {
+        tables: Some(!args.no_tables),
+        footnotes: Some(!args.no_footnotes),
+        strikethrough: Some(!args.no_strikethrough),
+        tasklists: Some(!args.no_tasklists),
+        smart_punctuation: Some(args.smart_punctuation),
+        heading_attributes: Some(args.heading_attributes),
+      }),
+      detect_language: Some(args.detect_language),
+      default_language: args.default_language.clone(),
+      max_highlight_bytes: args.max_highlight_bytes,
+      highlight_timeout_ms: args.highlight_timeout_ms,
+      raw_html: args.raw_html.clone(),
+      links: Some(LinkOptions {
+        base_url: args.base_url.clone(),
+        strip_md_extension: Some(args.strip_md_extension),
+        rewrites: Some(rewrites),
+        ..Default::default()
+      }),
+      images: (args.lazy_images || args.figures).then(|| ImageOptions {
+        lazy: Some(args.lazy_images),
+        async_decoding: Some(args.lazy_images),
+        figures: Some(args.figures),
+        ..Default::default()
+      }),
+      external_links: args
+        .external_links
+        .as_ref()
+        .map(|site_origin| ExternalLinkOptions {
+          site_origin: site_origin.clone(),
+          class: args.external_link_class.clone(),
+        }),
+      callouts: Some(args.callouts),
+      math: Some(args.math),
+      diagrams: Some(args.diagrams.clone()),
+      ..Default::default()
+    }),
+    theme: args.theme.clone(),
+    dark_theme: args.dark_theme.clone(),
+    languages: Some(languages),
+  })
+}
+
+/// Splits repeated KEY=VALUE arguments into a map
+fn pairs(args: &[String], usage: &str) -> Result<HashMap<String, String>> {
+  args
+    .iter()
+    .map(|arg| {
+      arg
+        .split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| eyre!("{usage}, got {arg:?}"))
+    })
+    .collect()
+}
+
+/// Expands the input arguments into documents, in the order they were given
+fn documents(inputs: &[String]) -> Result<Vec<Document>> {
+  if inputs.is_empty() {
+    return Ok(vec![stdin_document()]);
+  }
+
+  let mut documents = Vec::new();
+  for input in inputs {
+    if input == "-" {
+      documents.push(stdin_document());
+      continue;
+    }
+
+    let base = glob_base(input);
+    let mut matched = false;
+    for path in glob::glob(input).wrap_err_with(|| format!("invalid glob {input:?}"))? {
+      let path = path?;
+      if !path.is_file() {
+        continue;
+      }
+      matched = true;
+      documents.push(Document {
+        relative: relative_to(&path, &base),
+        source: Some(path),
+      });
+    }
+    if !matched {
+      bail!("no markdown files match {input:?}");
+    }
+  }
+  Ok(documents)
+}
+
+fn stdin_document() -> Document {
+  Document {
+    source: None,
+    relative: "stdin".into(),
+  }
+}
+
+/// The directory a glob pattern starts matching in, like "docs" for "docs/**/*.md". Output
+/// paths are relative to it. A plain file path is its own parent directory
+fn glob_base(pattern: &str) -> PathBuf {
+  let is_glob = |s: &str| s.contains(['*', '?', '[', '{']);
+  let path = Path::new(pattern);
+  if !is_glob(pattern) {
+    return path.parent().map(Path::to_path_buf).unwrap_or_default();
+  }
+
+  path
+    .components()
+    .take_while(|component| !is_glob(&component.as_os_str().to_string_lossy()))
+    .collect()
+}
+
+fn relative_to(path: &Path, base: &Path) -> PathBuf {
+  let relative = path.strip_prefix(base).unwrap_or(path);
+  // Never let a path climb out of the output directory
+  relative
+    .components()
+    .filter(|component| matches!(component, Component::Normal(_)))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_output_paths_are_relative_to_the_glob() {
+    assert_eq!(glob_base("docs/**/*.md"), PathBuf::from("docs"));
+    assert_eq!(glob_base("posts/hello.md"), PathBuf::from("posts"));
+    assert_eq!(glob_base("*.md"), PathBuf::new());
+    assert_eq!(
+      relative_to(Path::new("docs/guide/intro.md"), &glob_base("docs/**/*.md")),
+      PathBuf::from("guide/intro.md")
+    );
+    assert_eq!(
+      relative_to(Path::new("../notes/todo.md"), Path::new("docs")),
+      PathBuf::from("notes/todo.md")
+    );
+  }
+}
@@ -0,0 +1,141 @@
+use crate::Error;
+use lru::LruCache;
+use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+/// Bump whenever the markup produced by `highlight_code` changes, so stale entries persisted
+/// by an older femark are never served
+const CACHE_VERSION: u32 = 1;
+
+/// Number of highlighted blocks kept in memory when no capacity is configured
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+/// The caches of every renderer, by capacity and directory, so renderers with the same
+/// options, including the ones behind each `process_markdown_to_html` call, share one
+static SHARED: Lazy<Mutex<HashMap<SharedKey, Arc<HighlightCache>>>> = Lazy::new(Default::default);
+
+type SharedKey = (usize, Option<PathBuf>);
+
+/// A content addressed store for highlighted code blocks. Entries are keyed by a hash of the
+/// language, the source and the cache version, kept in memory with an LRU bound and
+/// optionally persisted to a directory so later builds can reuse them
+pub struct HighlightCache {
+  memory: Mutex<LruCache<String, Arc<str>>>,
+  directory: Option<PathBuf>,
+}
+
+impl HighlightCache {
+  pub fn new(capacity: usize, directory: Option<PathBuf>) -> Result<Self, Error> {
+    let capacity = NonZeroUsize::new(capacity)
+      .ok_or_else(|| Error::InvalidConfig("highlight cache capacity must be at least 1".into()))?;
+    if let Some(directory) = &directory {
+      fs::create_dir_all(directory).map_err(|e| {
+        Error::InvalidConfig(format!(
+          "could not create highlight cache directory {}: {e}",
+          directory.display()
+        ))
+      })?;
+    }
+    Ok(Self {
+      memory: Mutex::new(LruCache::new(capacity)),
+      directory,
+    })
+  }
+
+  /// The cache for `capacity` and `directory` that renderers share, created on first use
+  pub fn shared(capacity: usize, directory: Option<PathBuf>) -> Result<Arc<Self>, Error> {
+    let mut shared = SHARED.lock().unwrap();
+    let key = (capacity, directory);
+    if let Some(cache) = shared.get(&key) {
+      return Ok(Arc::clone(cache));
+    }
+    let cache = Arc::new(Self::new(key.0, key.1.clone())?);
+    shared.insert(key, Arc::clone(&cache));
+    Ok(cache)
+  }
+
+  /// The cache key for a block of `source` highlighted as `lang`
+  pub fn key(lang: &str, source: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(CACHE_VERSION.to_le_bytes());
+    hasher.update(env!("CARGO_PKG_VERSION"));
+    hasher.update([0]);
+    hasher.update(lang);
+    hasher.update([0]);
+    hasher.update(source);
+
+    let mut key = String::with_capacity(64);
+    for byte in hasher.finalize() {
+      write!(&mut key, "{byte:02x}").ok();
+    }
+    key
+  }
+
+  /// Looks a key up in memory first, then on disk
+  pub fn get(&self, key: &str) -> Option<Arc<str>> {
+    if let Some(hit) = self.memory.lock().unwrap().get(key) {
+      return Some(Arc::clone(hit));
+    }
+
+    let path = self.directory.as_ref()?.join(format!("{key}.html"));
+    let markup: Arc<str> = fs::read_to_string(path).ok()?.into();
+    self
+      .memory
+      .lock()
+      .unwrap()
+      .put(key.to_string(), Arc::clone(&markup));
+    Some(markup)
+  }
+
+  pub fn insert(&self, key: String, markup: &str) {
+    if let Some(directory) = &self.directory {
+      // Write to a temporary file and rename it into place, so a concurrent reader never
+      // sees half an entry
+      let path = directory.join(format!("{key}.html"));
+      let tmp = directory.join(format!(
+        "{key}.{}.{:?}.tmp",
+        std::process::id(),
+        std::thread::current().id()
+      ));
+      if let Err(e) = fs::write(&tmp, markup).and_then(|_| fs::rename(&tmp, &path)) {
+        warn!(
+          "Could not persist highlighted block to {}: {}",
+          path.display(),
+          e
+        );
+        fs::remove_file(&tmp).ok();
+      }
+    }
+    self.memory.lock().unwrap().put(key, markup.into());
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_cache_persists_to_directory() {
+    let directory = std::env::temp_dir().join(format!("femark-cache-test-{}", std::process::id()));
+    let key = HighlightCache::key("rust", "fn main() {}");
+    assert_ne!(key, HighlightCache::key("c", "fn main() {}"));
+
+    let cache = HighlightCache::new(8, Some(directory.clone())).unwrap();
+    assert!(cache.get(&key).is_none());
+    cache.insert(key.clone(), "<i class=hh4>fn</i>");
+    assert_eq!(cache.get(&key).as_deref(), Some("<i class=hh4>fn</i>"));
+
+    // A fresh cache over the same directory starts with an empty memory but finds the entry
+    let cache = HighlightCache::new(8, Some(directory.clone())).unwrap();
+    assert_eq!(cache.get(&key).as_deref(), Some("<i class=hh4>fn</i>"));
+
+    fs::remove_dir_all(directory).ok();
+  }
+}
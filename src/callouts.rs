@@ -0,0 +1,316 @@
+use pulldown_cmark::{html, Event, Tag};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+
+/// Kinds of callouts, from GitHub's `> [!NOTE]` and the `:::tip` containers of VuePress and
+/// Docusaurus. Anything else stays a blockquote
+const KINDS: &[&str] = &[
+  "note",
+  "tip",
+  "important",
+  "warning",
+  "caution",
+  "info",
+  "danger",
+];
+
+fn kind_of(name: &str) -> Option<&'static str> {
+  KINDS
+    .iter()
+    .find(|kind| kind.eq_ignore_ascii_case(name))
+    .copied()
+}
+
+/// Reads the `[!KIND]` marker at the start of `text`, returning the kind and what follows
+fn marker(text: &str) -> Option<(&'static str, &str)> {
+  let (name, rest) = text.strip_prefix("[!")?.split_once(']')?;
+  Some((kind_of(name)?, rest))
+}
+
+/// Whether more text could still turn `text` into a marker
+fn marker_prefix(text: &str) -> bool {
+  KINDS.iter().any(|kind| {
+    let marker = format!("[!{kind}]");
+    marker.len() > text.len() && marker[..text.len()].eq_ignore_ascii_case(text)
+  })
+}
+
+/// Keeps the `[!KIND]` markers of callouts away from what reads a document's text, like the
+/// word count and search sections, while the renderer walks it
+pub(crate) struct Markers {
+  enabled: bool,
+  state: MarkerState,
+}
+
+enum MarkerState {
+  Idle,
+  Quote,
+  // The text at the start of a blockquote that may yet be a marker
+  Paragraph(String),
+}
+
+impl Markers {
+  pub(crate) fn new(enabled: bool) -> Self {
+    Self {
+      enabled,
+      state: MarkerState::Idle,
+    }
+  }
+
+  /// Hands `ev` to `f`, without a marker's text. Text held back in case it was a marker
+  /// comes along with the event that shows it wasn't
+  pub(crate) fn plain(&mut self, ev: &Event, mut f: impl FnMut(&Event)) {
+    if !self.enabled {
+      return f(ev);
+    }
+    let state = std::mem::replace(&mut self.state, MarkerState::Idle);
+    match (state, ev) {
+      (_, Event::Start(Tag::BlockQuote)) => self.state = MarkerState::Quote,
+      (MarkerState::Quote, Event::Start(Tag::Paragraph)) => {
+        self.state = MarkerState::Paragraph(String::new())
+      }
+      (MarkerState::Paragraph(mut held), Event::Text(text)) => {
+        held.push_str(text);
+        if let Some((_, rest)) = marker(&held) {
+          let rest = rest.trim_start();
+          if !rest.is_empty() {
+            f(&Event::Text(rest.to_string().into()));
+          }
+        } else if marker_prefix(&held) {
+          self.state = MarkerState::Paragraph(held);
+        } else {
+          f(&Event::Text(held.into()));
+        }
+        return;
+      }
+      (MarkerState::Paragraph(held), _) if !held.is_empty() => f(&Event::Text(held.into())),
+      _ => {}
+    }
+    f(ev)
+  }
+}
+
+/// Rewrites `:::kind Title` containers as `> [!KIND] Title` blockquotes, so both syntaxes
+/// are rendered by `Callouts`. Fenced code is left alone
+pub(crate) fn containers(input: &str) -> Cow<'_, str> {
+  if !input.contains(":::") {
+    return Cow::Borrowed(input);
+  }
+
+  let mut out = String::with_capacity(input.len() + 64);
+  let mut depth = 0;
+  // The indentation of the outermost open container, like that of the list item it's in,
+  // which goes ahead of the quote markers
+  let mut lead = "";
+  // The character and length of the code fence we're in
+  let mut fence: Option<(char, usize)> = None;
+  for line in input.lines() {
+    let trimmed = line.trim_start();
+    let indent = &line[..line.len() - trimmed.len()];
+    // Lines inside a container keep what indentation they have past its own
+    let content = match depth {
+      0 => line,
+      _ => &line[indent.len().min(lead.len())..],
+    };
+    let marker = trimmed.chars().next().filter(|c| matches!(c, '`' | '~'));
+    if let Some(c) = marker {
+      let len = trimmed.chars().take_while(|&x| x == c).count();
+      match fence {
+        Some((open, open_len)) if open == c && len >= open_len => fence = None,
+        None if len >= 3 => fence = Some((c, len)),
+        _ => {}
+      }
+    } else if fence.is_none() && content.len() - trimmed.len() < 4 {
+      if let Some(rest) = trimmed.strip_prefix(":::") {
+        let rest = rest.trim_start_matches(':').trim();
+        if rest.is_empty() && depth > 0 {
+          depth -= 1;
+          // A blank line ends the quote, or the next line would continue it lazily
+          if depth > 0 {
+            out.push_str(lead);
+            out.push_str(&"> ".repeat(depth));
+          }
+          out.push('\n');
+          continue;
+        }
+        let (name, title) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        if let Some(kind) = kind_of(name) {
+          if depth == 0 {
+            lead = indent;
+          }
+          out.push_str(lead);
+          out.push_str(&"> ".repeat(depth + 1));
+          out.push_str(&format!(
+            "[!{}] {}\n",
+            kind.to_ascii_uppercase(),
+            title.trim()
+          ));
+          depth += 1;
+          continue;
+        }
+      }
+    }
+    if depth > 0 {
+      out.push_str(lead);
+      out.push_str(&"> ".repeat(depth));
+    }
+    out.push_str(content);
+    out.push('\n');
+  }
+  Cow::Owned(out)
+}
+
+/// Turns blockquotes whose first line is a `[!KIND]` marker into
+/// `<aside class="callout callout-kind">`, with a title and a slot for an icon. Text after
+/// the marker replaces the default title
+pub(crate) struct Callouts<'a, I> {
+  inner: I,
+  enabled: bool,
+  // Events read ahead that still need to be looked at
+  pending: VecDeque<Event<'a>>,
+  // For every open blockquote, whether it became a callout
+  open: Vec<bool>,
+}
+
+impl<'a, I: Iterator<Item = Event<'a>>> Callouts<'a, I> {
+  pub(crate) fn new(inner: I, enabled: bool) -> Self {
+    Self {
+      inner,
+      enabled,
+      pending: VecDeque::new(),
+      open: Vec::new(),
+    }
+  }
+
+  fn next_input(&mut self) -> Option<Event<'a>> {
+    self.pending.pop_front().or_else(|| self.inner.next())
+  }
+
+  /// Looks at the start of a blockquote, returning the callout's opening markup if it is one
+  fn callout(&mut self) -> Option<String> {
+    let first = self.next_input();
+    if !matches!(first, Some(Event::Start(Tag::Paragraph))) {
+      self.pending.extend(first);
+      return None;
+    }
+    let mut line = Vec::new();
+    let mut end = None;
+    while let Some(ev) = self.next_input() {
+      match ev {
+        Event::SoftBreak | Event::HardBreak | Event::End(Tag::Paragraph) => {
+          end = Some(ev);
+          break;
+        }
+        ev => line.push(ev),
+      }
+    }
+
+    // The brackets may come as text events of their own
+    let leading: usize = line
+      .iter()
+      .take_while(|ev| matches!(ev, Event::Text(_)))
+      .count();
+    let text: String = line[..leading]
+      .iter()
+      .map(|ev| match ev {
+        Event::Text(text) => text.as_ref(),
+        _ => "",
+      })
+      .collect();
+    let (kind, rest) = match marker(&text) {
+      Some(marker) => marker,
+      None => {
+        let line = std::iter::once(Event::Start(Tag::Paragraph))
+          .chain(line)
+          .chain(end);
+        let mut pending: VecDeque<_> = line.collect();
+        pending.append(&mut self.pending);
+        self.pending = pending;
+        return None;
+      }
+    };
+
+    let mut title = String::new();
+    let title_events = std::iter::once(Event::Text(rest.trim_start().to_string().into()))
+      .chain(line.into_iter().skip(leading));
+    html::push_html(&mut title, title_events);
+    if title.trim().is_empty() {
+      title = kind[..1].to_ascii_uppercase() + &kind[1..];
+    }
+    // The rest of the first paragraph follows the title
+    if !matches!(end, Some(Event::End(Tag::Paragraph)) | None) {
+      self.pending.push_front(Event::Start(Tag::Paragraph));
+    }
+    Some(format!(
+      "<aside class=\"callout callout-{kind}\">\n<p class=\"callout-title\"><span class=\"callout-icon\" aria-hidden=\"true\"></span>{}</p>\n",
+      title.trim()
+    ))
+  }
+}
+
+impl<'a, I: Iterator<Item = Event<'a>>> Iterator for Callouts<'a, I> {
+  type Item = Event<'a>;
+
+  fn next(&mut self) -> Option<Event<'a>> {
+    if !self.enabled {
+      return self.inner.next();
+    }
+    match self.next_input()? {
+      Event::Start(Tag::BlockQuote) => {
+        let callout = self.callout();
+        self.open.push(callout.is_some());
+        Some(match callout {
+          Some(html) => Event::Html(html.into()),
+          None => Event::Start(Tag::BlockQuote),
+        })
+      }
+      Event::End(Tag::BlockQuote) => Some(match self.open.pop() {
+        Some(true) => Event::Html("</aside>\n".into()),
+        _ => Event::End(Tag::BlockQuote),
+      }),
+      ev => Some(ev),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pulldown_cmark::Parser;
+
+  fn render(input: &str) -> String {
+    let input = containers(input);
+    let mut out = String::new();
+    html::push_html(&mut out, Callouts::new(Parser::new(&input), true));
+    out
+  }
+
+  #[test]
+  fn test_github_callouts() {
+    assert_eq!(
+      render("> [!NOTE]\n> Mind the *gap*.\n"),
+      "<aside class=\"callout callout-note\">\n<p class=\"callout-title\"><span class=\"callout-icon\" aria-hidden=\"true\"></span>Note</p>\n<p>Mind the <em>gap</em>.</p>\n</aside>\n"
+    );
+    assert!(
+      render("> [!WARNING] **Careful** now\n").contains("</span><strong>Careful</strong> now</p>")
+    );
+    assert!(render("> [!NOPE]\n> Quoted\n").starts_with("<blockquote>\n<p>[!NOPE]\nQuoted</p>"));
+    assert!(render("> Just a quote\n").starts_with("<blockquote>"));
+  }
+
+  #[test]
+  fn test_fenced_containers() {
+    let input = ":::tip Pro tip\nUse `femark`.\n\n```md\n:::\n```\n:::\nAfter\n";
+    let html = render(input);
+    assert!(html.starts_with("<aside class=\"callout callout-tip\">"));
+    assert!(html.contains("</span>Pro tip</p>"));
+    assert!(html.contains("<code class=\"language-md\">:::\n</code>"));
+    assert!(html.ends_with("</aside>\n<p>After</p>\n"));
+    // Containers in a list item stay in it
+    let html = render("- item\n\n  :::tip\n  body\n  :::\n- next\n");
+    assert!(html.starts_with("<ul>\n<li>\n<p>item</p>\n<aside class=\"callout callout-tip\">"));
+    assert!(html.ends_with("<p>body</p>\n</aside>\n</li>\n<li>\n<p>next</p>\n</li>\n</ul>\n"));
+    // Unknown kinds are left as they were written
+    assert_eq!(containers(":::custom\nx\n:::\n"), ":::custom\nx\n:::\n");
+  }
+}
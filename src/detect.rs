@@ -0,0 +1,223 @@
+use crate::Langs;
+use std::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(not(target_arch = "wasm32"))]
+use tree_sitter::{Node, Parser};
+
+/// Grammars tried when guessing the language of an unlabeled code block, in order of
+/// preference. When two grammars parse a block equally well the earlier one wins, so the
+/// stricter grammars (JSON before JavaScript, TypeScript before JavaScript) come first.
+#[cfg(not(target_arch = "wasm32"))]
+const CANDIDATES: &[&str] = &[
+  "json",
+  "toml",
+  "html",
+  "go",
+  "rust",
+  "c",
+  "python",
+  "typescript",
+  "javascript",
+];
+
+/// Guesses which registered language `source` is written in. Cheap heuristics such as
+/// shebangs run first, then every candidate grammar parses the source and the one with
+/// the fewest ERROR and MISSING nodes is picked.
+/// Returns the key the language is registered under, e.g. "rust". Parsing stops once
+/// `budget` is spent, keeping the best guess so far
+pub(crate) fn detect_language(
+  langs: &Langs,
+  source: &str,
+  budget: Option<Duration>,
+) -> Option<&'static str> {
+  if source.trim().is_empty() {
+    return None;
+  }
+  heuristic(source).or_else(|| parse_candidates(langs, source, budget))
+}
+
+/// Without grammars on wasm32, only the heuristics are left
+#[cfg(target_arch = "wasm32")]
+fn parse_candidates(
+  _langs: &Langs,
+  _source: &str,
+  _budget: Option<Duration>,
+) -> Option<&'static str> {
+  None
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn parse_candidates(langs: &Langs, source: &str, budget: Option<Duration>) -> Option<&'static str> {
+  let deadline = budget.map(|budget| Instant::now() + budget);
+  let mut parser = Parser::new();
+  let mut best: Option<(&'static str, usize)> = None;
+  for &candidate in CANDIDATES {
+    if let Some(deadline) = deadline {
+      let left = deadline.saturating_duration_since(Instant::now());
+      if left.is_zero() {
+        break;
+      }
+      parser.set_timeout_micros(left.as_micros().max(1) as u64);
+    }
+    let conf = match langs.get(candidate).and_then(|l| l.conf.as_ref()) {
+      Some(conf) => conf,
+      None => continue,
+    };
+    if parser.set_language(conf.language).is_err() {
+      continue;
+    }
+    let tree = match parser.parse(source, None) {
+      Some(tree) => tree,
+      None => {
+        // Out of time, and the next parse must not resume this one
+        parser.reset();
+        continue;
+      }
+    };
+    let root = tree.root_node();
+    let (errors, named) = count_nodes(root);
+    // A grammar that barely recognizes the block is a worse guess than no guess at all
+    if named == 0 || errors * 20 > named {
+      continue;
+    }
+    // Anything parses as HTML text without a single error, so it needs a real element
+    if candidate == "html" && !has_kind(root, "element") {
+      continue;
+    }
+    if best.is_none_or(|(_, fewest)| errors < fewest) {
+      best = Some((candidate, errors));
+    }
+  }
+
+  best.map(|(lang, _)| lang)
+}
+
+/// Recognizes blocks that announce their language, like scripts with a shebang
+fn heuristic(source: &str) -> Option<&'static str> {
+  let first_line = source.trim_start().lines().next().unwrap_or_default();
+
+  if let Some(shebang) = first_line.strip_prefix("#!") {
+    let interpreter = shebang
+      .split_whitespace()
+      .flat_map(|part| part.rsplit('/').next())
+      .find(|part| *part != "env")?;
+    return match interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.') {
+      "sh" | "bash" | "zsh" | "dash" | "ksh" => Some("shell"),
+      "python" => Some("python"),
+      "node" | "deno" | "bun" => Some("javascript"),
+      "pwsh" => Some("pwsh-script"),
+      _ => None,
+    };
+  }
+  if first_line.starts_with("$ ") {
+    return Some("shell");
+  }
+  if first_line.starts_with("FROM ") {
+    return Some("dockerfile");
+  }
+  if source.contains("fn main()") {
+    return Some("rust");
+  }
+  if first_line.starts_with("package main") {
+    return Some("go");
+  }
+  if first_line.starts_with("#include") {
+    return Some("c");
+  }
+  let lower = first_line.to_ascii_lowercase();
+  if lower.starts_with("<!doctype html") || lower.starts_with("<html") {
+    return Some("html");
+  }
+
+  None
+}
+
+/// Counts ERROR and MISSING nodes along with the total number of named nodes in a tree
+#[cfg(not(target_arch = "wasm32"))]
+fn count_nodes(root: Node) -> (usize, usize) {
+  let mut errors = 0;
+  let mut named = 0;
+  walk(root, |node| {
+    if node.is_error() || node.is_missing() {
+      errors += 1;
+    }
+    if node.is_named() {
+      named += 1;
+    }
+  });
+  (errors, named)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn has_kind(root: Node, kind: &str) -> bool {
+  let mut found = false;
+  walk(root, |node| found |= node.kind() == kind);
+  found
+}
+
+/// Visits every node of a tree, depth first
+#[cfg(not(target_arch = "wasm32"))]
+fn walk(root: Node, mut visit: impl FnMut(Node)) {
+  let mut cursor = root.walk();
+  'walk: loop {
+    visit(cursor.node());
+
+    if cursor.goto_first_child() {
+      continue;
+    }
+    while !cursor.goto_next_sibling() {
+      if !cursor.goto_parent() {
+        break 'walk;
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::LANGS;
+
+  #[test]
+  fn test_detect_language() {
+    assert_eq!(
+      detect_language(&LANGS, "#!/usr/bin/env bash\necho hi\n", None),
+      Some("shell")
+    );
+    assert_eq!(
+      detect_language(&LANGS, "fn main() {\n    println!(\"hi\");\n}\n", None),
+      Some("rust")
+    );
+    assert_eq!(
+      detect_language(&LANGS, "{\"name\": \"femark\", \"version\": 1}\n", None),
+      Some("json")
+    );
+    assert_eq!(detect_language(&LANGS, "   \n", None), None);
+  }
+
+  #[test]
+  fn test_detect_language_by_parsing() {
+    assert_eq!(
+      detect_language(&LANGS, "def foo(x):\n    return x + 1\n", None),
+      Some("python")
+    );
+    assert_eq!(
+      detect_language(
+        &LANGS,
+        "func add(a int, b int) int {\n\treturn a + b\n}\n",
+        None
+      ),
+      Some("go")
+    );
+    assert_eq!(
+      detect_language(&LANGS, "<div class=\"note\">\n  <p>Hi</p>\n</div>\n", None),
+      Some("html")
+    );
+    // Text without any structure is better left unlabeled
+    let listing = "total 8\n-rw-r--r--  1 me  staff  12 Jan  1 10:00 a.txt\n";
+    assert_eq!(detect_language(&LANGS, listing, None), None);
+    let prose = "This is just some prose that somebody\nput in a code block.\n";
+    assert_eq!(detect_language(&LANGS, prose, None), None);
+  }
+}
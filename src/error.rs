@@ -0,0 +1,81 @@
+use std::fmt;
+
+/// Errors returned while configuring a renderer or rendering a document
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+  #[error("invalid renderer configuration: {0}")]
+  InvalidConfig(String),
+  #[error("hook failed: {0}")]
+  Hook(String),
+  #[error("could not write output: {0}")]
+  Write(#[from] std::io::Error),
+  #[error("renderer panicked: {0}")]
+  Panicked(String),
+}
+
+impl Error {
+  /// A stable name for the kind of error, set as `code` on errors thrown to JavaScript
+  pub fn code(&self) -> &'static str {
+    match self {
+      Self::InvalidConfig(_) => "INVALID_CONFIG",
+      Self::Hook(_) => "HOOK_FAILED",
+      Self::Write(_) => "WRITE_FAILED",
+      Self::Panicked(_) => "PANICKED",
+    }
+  }
+}
+
+/// Why a code block couldn't be highlighted. These never fail a render: the block is
+/// escaped instead, and the error is reported as a warning
+#[derive(Debug, thiserror::Error)]
+pub enum HighlightError {
+  #[error("unknown language {0:?}")]
+  UnknownLanguage(String),
+  #[error("no grammar for language {0:?}")]
+  NoGrammar(String),
+  #[error("the grammar for {lang:?} failed to load: {message}")]
+  BrokenGrammar { lang: String, message: String },
+  #[error("could not highlight {lang:?}: {message}")]
+  Highlighter { lang: String, message: String },
+  #[error("the {lang:?} block is {bytes} bytes long, over the highlighting limit of {limit}")]
+  TooLarge {
+    lang: String,
+    bytes: usize,
+    limit: usize,
+  },
+  #[error("highlighting the {lang:?} block took longer than {budget_ms}ms")]
+  TimedOut { lang: String, budget_ms: u128 },
+  #[error("could not write highlighted code")]
+  Write(#[from] fmt::Error),
+}
+
+impl HighlightError {
+  /// A stable name for the kind of error, set as `code` on warnings
+  pub fn code(&self) -> &'static str {
+    match self {
+      Self::UnknownLanguage(_) => "UNKNOWN_LANGUAGE",
+      Self::NoGrammar(_) => "NO_GRAMMAR",
+      Self::BrokenGrammar { .. } => "BROKEN_GRAMMAR",
+      Self::Highlighter { .. } => "HIGHLIGHT_FAILED",
+      Self::TooLarge { .. } => "TOO_LARGE",
+      Self::TimedOut { .. } => "TIMED_OUT",
+      Self::Write(_) => "WRITE_FAILED",
+    }
+  }
+
+  /// Outcomes that are expected and not worth a warning, like unlabeled blocks or languages
+  /// that are only tagged, such as shell sessions
+  pub(crate) fn benign(&self) -> bool {
+    match self {
+      Self::UnknownLanguage(lang) => lang.is_empty(),
+      Self::NoGrammar(_) => true,
+      _ => false,
+    }
+  }
+
+  /// Whether the block went over the configured limits, in which case it is escaped rather
+  /// than handed to a highlight hook
+  pub(crate) fn exceeded_limits(&self) -> bool {
+    matches!(self, Self::TooLarge { .. } | Self::TimedOut { .. })
+  }
+}
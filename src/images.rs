@@ -0,0 +1,181 @@
+use crate::{Error, Hooks, ImageInfo, ImageOptions};
+use pulldown_cmark::escape::{escape_href, escape_html};
+use pulldown_cmark::{CowStr, Event, Tag};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+
+/// How images are rendered, once validated
+pub(crate) struct ImageRules {
+  lazy: bool,
+  async_decoding: bool,
+  figures: bool,
+  manifest: HashMap<String, ImageInfo>,
+}
+
+impl ImageRules {
+  pub(crate) fn new(options: &ImageOptions) -> Self {
+    Self {
+      lazy: options.lazy.unwrap_or(false),
+      async_decoding: options.async_decoding.unwrap_or(false),
+      figures: options.figures.unwrap_or(false),
+      manifest: options.manifest.clone().unwrap_or_default(),
+    }
+  }
+}
+
+struct Image<'a> {
+  src: CowStr<'a>,
+  title: CowStr<'a>,
+  alt: String,
+}
+
+/// Renders the images of an event stream with their extra attributes, and wraps titled
+/// images that stand alone in a paragraph in a `<figure>`. Streams pass through untouched
+/// when no rules are configured
+pub(crate) struct Images<'r, 'a, I> {
+  inner: I,
+  rules: Option<&'r ImageRules>,
+  hooks: &'r dyn Hooks,
+  // The first error of the image_info hook, raised once rendering ends
+  error: &'r RefCell<Option<Error>>,
+  // An event read ahead while looking for a figure, to be handled next
+  pushed_back: Option<Event<'a>>,
+  ready: VecDeque<Event<'a>>,
+}
+
+impl<'r, 'a, I: Iterator<Item = Event<'a>>> Images<'r, 'a, I> {
+  pub(crate) fn new(
+    inner: I,
+    rules: Option<&'r ImageRules>,
+    hooks: &'r dyn Hooks,
+    error: &'r RefCell<Option<Error>>,
+  ) -> Self {
+    Self {
+      inner,
+      rules,
+      hooks,
+      error,
+      pushed_back: None,
+      ready: VecDeque::new(),
+    }
+  }
+
+  fn next_input(&mut self) -> Option<Event<'a>> {
+    self.pushed_back.take().or_else(|| self.inner.next())
+  }
+
+  /// Reads the alt text of an image, up to its end
+  fn image(&mut self, src: CowStr<'a>, title: CowStr<'a>) -> Image<'a> {
+    let mut alt = String::new();
+    let mut depth = 0;
+    while let Some(ev) = self.next_input() {
+      match ev {
+        Event::Start(Tag::Image(..)) => depth += 1,
+        Event::End(Tag::Image(..)) if depth == 0 => break,
+        Event::End(Tag::Image(..)) => depth -= 1,
+        Event::Text(text) | Event::Code(text) => alt.push_str(&text),
+        Event::SoftBreak | Event::HardBreak => alt.push(' '),
+        _ => {}
+      }
+    }
+    Image { src, title, alt }
+  }
+
+  fn render(&self, rules: &ImageRules, image: &Image, figure: bool) -> String {
+    let info = match self.hooks.image_info(&image.src) {
+      Ok(Some(info)) => Some(info),
+      Ok(None) => rules.manifest.get(image.src.as_ref()).cloned(),
+      Err(e) => {
+        self.error.borrow_mut().get_or_insert(e);
+        None
+      }
+    };
+
+    let mut out = String::new();
+    if figure {
+      out.push_str("<figure>");
+    }
+    out.push_str("<img src=\"");
+    escape_href(&mut out, &image.src).ok();
+    out.push_str("\" alt=\"");
+    escape_html(&mut out, &image.alt).ok();
+    out.push('"');
+    // A figure's caption already says what the title would
+    if !figure && !image.title.is_empty() {
+      out.push_str(" title=\"");
+      escape_html(&mut out, &image.title).ok();
+      out.push('"');
+    }
+    if let Some(info) = info {
+      if let Some(width) = info.width {
+        out.push_str(&format!(" width=\"{width}\""));
+      }
+      if let Some(height) = info.height {
+        out.push_str(&format!(" height=\"{height}\""));
+      }
+      for (name, value) in [("srcset", &info.srcset), ("sizes", &info.sizes)] {
+        if let Some(value) = value {
+          out.push_str(&format!(" {name}=\""));
+          escape_html(&mut out, value).ok();
+          out.push('"');
+        }
+      }
+    }
+    if rules.lazy {
+      out.push_str(r#" loading="lazy""#);
+    }
+    if rules.async_decoding {
+      out.push_str(r#" decoding="async""#);
+    }
+    out.push_str(" />");
+    if figure {
+      out.push_str("<figcaption>");
+      escape_html(&mut out, &image.title).ok();
+      out.push_str("</figcaption></figure>\n");
+    }
+    out
+  }
+}
+
+impl<'r, 'a, I: Iterator<Item = Event<'a>>> Iterator for Images<'r, 'a, I> {
+  type Item = Event<'a>;
+
+  fn next(&mut self) -> Option<Event<'a>> {
+    let rules = match self.rules {
+      Some(rules) => rules,
+      None => return self.inner.next(),
+    };
+    if let Some(ev) = self.ready.pop_front() {
+      return Some(ev);
+    }
+
+    match self.next_input()? {
+      Event::Start(Tag::Image(_, src, title)) => {
+        let image = self.image(src, title);
+        Some(Event::Html(self.render(rules, &image, false).into()))
+      }
+      Event::Start(Tag::Paragraph) if rules.figures => match self.next_input() {
+        Some(Event::Start(Tag::Image(_, src, title))) if !title.is_empty() => {
+          let image = self.image(src, title);
+          match self.next_input() {
+            Some(Event::End(Tag::Paragraph)) => {
+              Some(Event::Html(self.render(rules, &image, true).into()))
+            }
+            // There's more to the paragraph, so the image stays inline
+            after => {
+              let html = self.render(rules, &image, false);
+              self.ready.push_back(Event::Html(html.into()));
+              self.pushed_back = after;
+              Some(Event::Start(Tag::Paragraph))
+            }
+          }
+        }
+        first => {
+          self.pushed_back = first;
+          Some(Event::Start(Tag::Paragraph))
+        }
+      },
+      ev => Some(ev),
+    }
+  }
+}
@@ -1,29 +1,78 @@
+mod cache;
+mod callouts;
+mod detect;
+mod error;
+mod images;
+mod links;
+mod math;
+#[cfg(feature = "node")]
+pub mod node;
+mod outline;
+#[cfg(feature = "python")]
+pub mod python;
+mod renderer;
+mod search;
+mod summary;
+mod themes;
+#[cfg(not(target_arch = "wasm32"))]
 mod tree_sitter_collection;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(not(target_arch = "wasm32"))]
+mod watchdog;
 
+#[cfg(feature = "node")]
 #[macro_use]
 extern crate napi_derive;
 
+pub use crate::error::{Error, HighlightError};
+pub use crate::links::ResolvedLink;
+pub use crate::outline::{
+  Outline, OutlineCodeBlock, OutlineFootnote, OutlineHeading, OutlineImage, OutlineLink,
+};
+pub use crate::renderer::{Hooks, NoHooks, Renderer};
+pub use crate::search::SearchSection;
+pub use crate::summary::Excerpt;
+#[cfg(not(target_arch = "wasm32"))]
 use crate::tree_sitter_collection::TreeSitterCollection;
-use eyre::Result;
 use once_cell::sync::Lazy;
-use pulldown_cmark::{html, CodeBlockKind, Event, HeadingLevel, Options, Parser, Tag};
 use serde::{Deserialize, Serialize};
-use slug::slugify;
-use std::{collections::HashMap, io::Cursor, sync::Arc};
-use tracing::{debug, warn};
-use tree_sitter::QueryError;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tracing::warn;
+#[cfg(not(target_arch = "wasm32"))]
 use tree_sitter_highlight::{Highlight, HighlightConfiguration, HighlightEvent, Highlighter};
 
-fn options() -> Options {
-  let mut options = Options::empty();
-  options.insert(Options::ENABLE_TABLES);
-  options.insert(Options::ENABLE_FOOTNOTES);
-  options.insert(Options::ENABLE_STRIKETHROUGH);
-  options.insert(Options::ENABLE_TASKLISTS);
-  options
-}
-
-static LANGS: Lazy<Langs> = Lazy::new(|| Langs::new().unwrap());
+/// Stands in for the grammars on wasm32, where no language has one
+#[cfg(target_arch = "wasm32")]
+type HighlightConfiguration = std::convert::Infallible;
+
+/// Highlight names recognized by the grammars. A highlight is rendered with the class
+/// `hhN`, where N is its index in this list
+pub const HIGHLIGHT_NAMES: [&str; 21] = [
+  "attribute",
+  "constant",
+  "function.builtin",
+  "function",
+  "keyword",
+  "operator",
+  "property",
+  "punctuation",
+  "punctuation.bracket",
+  "punctuation.delimiter",
+  "string",
+  "string.special",
+  "tag",
+  "type",
+  "type.builtin",
+  "variable",
+  "variable.builtin",
+  "variable.parameter",
+  "comment",
+  "macro",
+  "label",
+];
+
+static LANGS: Lazy<Langs> = Lazy::new(Langs::new);
 
 pub struct Langs {
   langs: HashMap<&'static str, Arc<Lang>>,
@@ -31,224 +80,87 @@ pub struct Langs {
 
 pub struct Lang {
   conf: Option<HighlightConfiguration>,
+  grammar_error: Option<String>,
   name: &'static str,
 }
 
-impl Langs {
-  pub fn new() -> std::result::Result<Self, QueryError> {
-    let highlight_names = [
-      "attribute",
-      "constant",
-      "function.builtin",
-      "function",
-      "keyword",
-      "operator",
-      "property",
-      "punctuation",
-      "punctuation.bracket",
-      "punctuation.delimiter",
-      "string",
-      "string.special",
-      "tag",
-      "type",
-      "type.builtin",
-      "variable",
-      "variable.builtin",
-      "variable.parameter",
-      "comment",
-      "macro",
-      "label",
-    ]
-    .iter()
-    .cloned()
-    .map(String::from)
-    .collect::<Vec<_>>();
+/// Every registered language: the keys code blocks can be labeled with, and the name shown
+/// in the language tag. The grammar is looked up by the first key
+const LANGUAGES: &[(&[&str], &str)] = &[
+  (&["go"], "Go code"),
+  (&["c"], "C code"),
+  (&["rust"], "Rust code"),
+  (&["javascript", "js"], "JavaScript code"),
+  (&["jsx"], "Javascript React code"),
+  (&["typescript", "ts"], "TypeScript code"),
+  (&["tsx"], "TypeScript React code"),
+  (&["toml"], "TOML markup"),
+  (&["html"], "HTML"),
+  (&["xml"], "XML"),
+  (&["shell"], "Shell session"),
+  (&["pwsh"], "PowerShell session"),
+  (&["pwsh-script"], "PowerShell script"),
+  (&["raw"], ""),
+  (&["Python code", "python", "py"], "Python"),
+  (&["Dockerfile", "dockerfile"], "Dockerfile"),
+  (&["JSON", "json"], "JSON"),
+];
+
+/// The grammar highlighting the language registered under `key`, if it has one. Fails when
+/// the grammar's queries don't compile
+#[cfg(not(target_arch = "wasm32"))]
+fn grammar(key: &str) -> std::result::Result<Option<HighlightConfiguration>, String> {
+  let collection = match key {
+    "go" => TreeSitterCollection::go(),
+    "c" => TreeSitterCollection::c(),
+    "rust" => TreeSitterCollection::rust(),
+    "javascript" => TreeSitterCollection::javascript(),
+    "jsx" => TreeSitterCollection::jsx(),
+    "typescript" => TreeSitterCollection::typescript(),
+    "tsx" => TreeSitterCollection::tsx(),
+    "toml" => TreeSitterCollection::toml(),
+    "html" | "xml" => TreeSitterCollection::html(),
+    "Python code" => TreeSitterCollection::python(),
+    "Dockerfile" => TreeSitterCollection::dockerfile(),
+    "JSON" => TreeSitterCollection::json(),
+    _ => return Ok(None),
+  };
+  let mut conf = collection.map_err(|e| e.to_string())?.conf;
+  conf.configure(&HIGHLIGHT_NAMES);
+  Ok(Some(conf))
+}
 
+#[cfg(target_arch = "wasm32")]
+fn grammar(_key: &str) -> std::result::Result<Option<HighlightConfiguration>, String> {
+  Ok(None)
+}
+
+impl Langs {
+  pub fn new() -> Self {
     let mut res = Self {
       langs: Default::default(),
     };
 
-    {
-      let mut c = TreeSitterCollection::go().conf;
-      c.configure(&highlight_names);
-      let c = Lang {
-        conf: Some(c),
-        name: "Go code",
-      };
-      let c = Arc::new(c);
-      res.langs.insert("go", c);
-    }
-    {
-      let mut c = TreeSitterCollection::c().conf;
-      c.configure(&highlight_names);
-      let c = Lang {
-        conf: Some(c),
-        name: "C code",
-      };
-      let c = Arc::new(c);
-      res.langs.insert("c", c);
-    }
-    {
-      let mut c = TreeSitterCollection::rust().conf;
-      c.configure(&highlight_names);
-      let c = Lang {
-        conf: Some(c),
-        name: "Rust code",
-      };
-      let c = Arc::new(c);
-      res.langs.insert("rust", c);
-    }
-    {
-      let mut c = TreeSitterCollection::javascript().conf;
-      c.configure(&highlight_names);
-
-      let c = Lang {
-        conf: Some(c),
-        name: "JavaScript code",
-      };
-      let c = Arc::new(c);
-      res.langs.insert("javascript", Arc::clone(&c));
-      res.langs.insert("js", c);
-    }
-    {
-      let mut c = TreeSitterCollection::jsx().conf;
-      c.configure(&highlight_names);
-      let c = Lang {
-        conf: Some(c),
-        name: "Javascript React code",
-      };
-      let c = Arc::new(c);
-      res.langs.insert("jsx", c);
-    }
-    {
-      let mut c = TreeSitterCollection::typescript().conf;
-      c.configure(&highlight_names);
-      let c = Lang {
-        conf: Some(c),
-        name: "TypeScript code",
-      };
-      let c = Arc::new(c);
-      res.langs.insert("typescript", Arc::clone(&c));
-      res.langs.insert("ts", c);
-    }
-    {
-      let mut c = TreeSitterCollection::tsx().conf;
-      c.configure(&highlight_names);
-      let c = Lang {
-        conf: Some(c),
-        name: "TypeScript React code",
-      };
-      let c = Arc::new(c);
-      res.langs.insert("tsx", c);
-    }
-    {
-      let mut c = TreeSitterCollection::toml().conf;
-      c.configure(&highlight_names);
-      let c = Lang {
-        conf: Some(c),
-        name: "TOML markup",
-      };
-      let c = Arc::new(c);
-      res.langs.insert("toml", c);
-    }
-    {
-      let mut c = TreeSitterCollection::html().conf;
-      c.configure(&highlight_names);
-      let c = Lang {
-        conf: Some(c),
-        name: "HTML",
-      };
-      let c = Arc::new(c);
-      res.langs.insert("html", c);
-    }
-    {
-      let mut c = TreeSitterCollection::html().conf;
-      c.configure(&highlight_names);
-      let c = Lang {
-        conf: Some(c),
-        name: "XML",
-      };
-      let c = Arc::new(c);
-      res.langs.insert("xml", c);
-    }
-    {
-      res.langs.insert(
-        "shell",
-        Arc::new(Lang {
-          conf: None,
-          name: "Shell session",
-        }),
-      );
-    }
-    {
-      res.langs.insert(
-        "pwsh",
-        Arc::new(Lang {
-          conf: None,
-          name: "PowerShell session",
-        }),
-      );
-    }
-    {
-      res.langs.insert(
-        "pwsh-script",
-        Arc::new(Lang {
-          conf: None,
-          name: "PowerShell script",
-        }),
-      );
-    }
-    {
-      res.langs.insert(
-        "raw",
-        Arc::new(Lang {
-          conf: None,
-          name: "",
-        }),
-      );
-    }
-    {
-      let mut c = TreeSitterCollection::python().conf;
-      c.configure(&highlight_names);
-      let c = Lang {
-        conf: Some(c),
-        name: "Python",
-      };
-      let c = Arc::new(c);
-      res.langs.insert("Python code", c);
-    }
-    //   {
-    //     let mut c = TreeSitterCollection::yaml().conf;
-    //     c.configure(&highlight_names);
-    //     let c = Lang {
-    //       conf: Some(c),
-    //       name: "YAML",
-    //     };
-    //     let c = Arc::new(c);
-    //     res.langs.insert("yml", c);
-    //   }
-    {
-      let mut c = TreeSitterCollection::dockerfile().conf;
-      c.configure(&highlight_names);
-      let c = Lang {
-        conf: Some(c),
-        name: "Dockerfile",
-      };
-      let c = Arc::new(c);
-      res.langs.insert("Dockerfile", c);
-    }
-    {
-      let mut c = TreeSitterCollection::json().conf;
-      c.configure(&highlight_names);
-      let c = Lang {
-        conf: Some(c),
-        name: "JSON",
+    for &(keys, name) in LANGUAGES {
+      // A broken grammar only costs its own language the highlighting
+      let (conf, grammar_error) = match grammar(keys[0]) {
+        Ok(conf) => (conf, None),
+        Err(e) => {
+          warn!("Could not load the grammar for {}: {}", keys[0], e);
+          (None, Some(e))
+        }
       };
-      let c = Arc::new(c);
-      res.langs.insert("JSON", c);
+      let lang = Arc::new(Lang {
+        conf,
+        grammar_error,
+        name,
+      });
+      for &key in keys {
+        res.langs.insert(key, Arc::clone(&lang));
+      }
     }
 
-    Ok(res)
+    res
   }
 
   pub fn get(&self, k: &str) -> Option<&Lang> {
@@ -256,13 +168,19 @@ impl Langs {
   }
 }
 
+impl Default for Langs {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 pub type Toc = Vec<TocEntry>;
 
 #[derive(Serialize, Deserialize)]
 pub struct TocEntry {
   // 1 through 6
   level: u8,
-  // something like "The basics"
+  // the heading's inline HTML without its links, something like "The <em>basics</em>"
   text: String,
   // something like "the-basics"
   slug: String,
@@ -272,7 +190,7 @@ pub struct TocEntry {
 fn generate_toc(toc: &Toc) -> Option<String> {
   let mut toc_html = String::new();
 
-  if toc.len() > 0 {
+  if !toc.is_empty() {
     toc_html.push_str("<ul class=\"table-of-contents\">");
     for entry in toc {
       toc_html.push_str(&format!(
@@ -286,241 +204,346 @@ fn generate_toc(toc: &Toc) -> Option<String> {
   None
 }
 
-#[napi(object)]
+/// A rendered document
+#[cfg_attr(feature = "node", napi(object))]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct HTMLOutput {
   pub toc: Option<String>,
   pub content: String,
+  /// Problems that didn't stop the document from rendering
+  pub warnings: Vec<Warning>,
+  /// Link and image targets that neither the link rules nor the `resolveLink` hook could
+  /// resolve, in the order they appear. They are rendered as written
+  pub unresolved_links: Vec<String>,
+  /// The headings, links, images, footnotes and code blocks of the document
+  pub outline: Outline,
+  /// Words of prose, leaving out code blocks and image descriptions
+  pub word_count: u32,
+  /// Minutes it takes to read the prose, rounded up
+  pub reading_time_minutes: u32,
+  pub excerpt: Excerpt,
+  /// The document split at its headings into plain text, when `searchIndex` is set
+  pub sections: Option<Vec<SearchSection>>,
 }
-/// Processes markdown to html and syntax highlights the code blocks
-/// Takes in a string and returns an object containing the content HTML and the toc html
-/// Input: string
-/// Output: {toc: string, content: string}
-#[napi]
-pub fn process_markdown_to_html(input: String) -> Result<HTMLOutput, napi::bindgen_prelude::Error> {
-  let parser = Parser::new_ext(&input, options());
-  let stream = parser;
-  let langs = &LANGS;
-  let mut toc: Toc = Vec::new();
-  let mut output: Vec<u8> = Vec::new();
-  //   let stream = WideImages::new(parser);
-
-  struct Code {
-    lang: String,
-    source: String,
-  }
-  let mut current_code: Option<Code> = None;
-
-  struct Heading {
-    level: HeadingLevel,
-    #[allow(dead_code)]
-    frag: Option<String>,
-    #[allow(dead_code)]
-    class: Vec<String>,
-    markup: String,
-    plain_text: String,
+
+/// Something that didn't stop a document from rendering but likely needs attention, like
+/// a code block in an unknown language
+#[cfg_attr(feature = "node", napi(object))]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Warning {
+  /// A stable name for the kind of problem, e.g. "UNKNOWN_LANGUAGE"
+  pub code: String,
+  pub message: String,
+}
+
+impl From<HighlightError> for Warning {
+  fn from(error: HighlightError) -> Self {
+    Self {
+      code: error.code().into(),
+      message: error.to_string(),
+    }
   }
-  let mut current_heading: Option<Heading> = None;
-
-  let mut in_blockquote = false;
-  let mut in_figcaption = false;
-
-  let stream = stream.map(|ev| {
-    debug!(?ev, "Got markdown event");
-    match &ev {
-      Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(lang))) => {
-        current_code = Some(Code {
-          lang: lang.to_string(),
-          source: Default::default(),
-        });
-        return Event::Text("".into());
-      }
-      Event::Start(Tag::Heading(level, frag, class)) if !in_blockquote && !in_figcaption => {
-        current_heading = Some(Heading {
-          level: *level,
-          frag: frag.map(ToOwned::to_owned),
-          class: class.iter().map(ToString::to_string).collect(),
-          markup: "".into(),
-          plain_text: "".into(),
-        });
-        return Event::Text("".into());
-      }
-      Event::Start(Tag::BlockQuote) => {
-        in_blockquote = true;
-      }
-      Event::Code(contents) => {
-        if let Some(current_heading) = &mut current_heading {
-          current_heading
-            .markup
-            .push_str(&format!("<code>{contents}</code>"));
-          current_heading.plain_text.push_str(contents.as_ref());
-          return Event::Text("".into());
-        }
-      }
-      Event::Html(html) => {
-        if html.contains("<figcaption>") {
-          in_figcaption = true;
-        }
-        if html.contains("</figcaption>") {
-          in_figcaption = false;
-        }
-      }
-      Event::End(Tag::CodeBlock(CodeBlockKind::Fenced(_))) => {
-        if let Some(current) = current_code.take() {
-          let mut out: String = String::new();
-          use std::fmt::Write;
-
-          let lang = langs.get(&current.lang);
-          write!(&mut out, r#"<div class="code-block">"#,).ok();
-
-          let tag = lang.map(|l| l.name).unwrap_or(&current.lang);
-          if !tag.is_empty() {
-            write!(
-              &mut out,
-              r#"<div class="language-tag">{}</div>"#,
-              lang.map(|l| l.name).unwrap_or(&current.lang)
-            )
-            .ok();
-          }
-          write!(
-            &mut out,
-            r#"<pre class="code-block-inner" data-lang={:?}>"#,
-            current.lang
-          )
-          .ok();
-
-          if let Err(e) = highlight_code(&mut out, &current.source, &lang) {
-            if !e.benign() {
-              warn!("Highlight error: {}", e);
-            }
-            write_code_escaped(&mut out, &current.source).ok();
-          }
-          write!(&mut out, "</pre></div>").ok();
+}
 
-          return Event::Html(out.into());
-        }
-      }
-      Event::End(Tag::Heading(_, _, _)) => {
-        if let Some(heading) = current_heading.take() {
-          let tag = match heading.level {
-            HeadingLevel::H1 => "h1",
-            HeadingLevel::H2 => "h2",
-            HeadingLevel::H3 => "h3",
-            HeadingLevel::H4 => "h4",
-            HeadingLevel::H5 => "h5",
-            HeadingLevel::H6 => "h6",
-          };
-          let markup = &heading.markup;
-          let anchor = slugify(&heading.plain_text);
-          let href = format!("#{anchor}");
-
-          let toc_entry = TocEntry {
-            level: heading.level as u8,
-            text: heading.markup.clone(),
-            slug: anchor.clone(),
-          };
-          toc.push(toc_entry);
-
-          return Event::Html(
-            format!(
-              r#"
-                        <{tag}>
-                            <a id="{anchor}" class="anchor" href="{href}">
-                                {markup}
-                            </a>
-                        </{tag}>
-                        "#
-            )
-            .into(),
-          );
-        }
-      }
-      Event::End(Tag::BlockQuote) => {
-        in_blockquote = false;
-      }
-      Event::Text(text) => {
-        if let Some(current) = current_code.as_mut() {
-          current.source.push_str(text);
-          return Event::Text("".into());
-        } else if let Some(current) = current_heading.as_mut() {
-          current.markup.push_str(text);
-          current.plain_text.push_str(text);
-          return Event::Text("".into());
-        }
-      }
-      _ => {}
+// napi only reads the attributes written on a field, never ones behind `cfg_attr`, so the
+// Node build passes the TypeScript type of `rawHtml` in. The struct is written here rather
+// than passed to the macro, which would cost napi the doc comments
+macro_rules! markdown_options {
+  ($(#[$raw_html:meta])*) => {
+    /// Options for rendering a single document
+    #[cfg_attr(feature = "node", napi(object))]
+    #[derive(Default, Clone, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct MarkdownOptions {
+      /// Which markdown extensions to enable
+      pub extensions: Option<MarkdownExtensions>,
+      /// Guess the language of indented code blocks and fences without an info string,
+      /// and highlight them as if they had been labeled. Off by default
+      pub detect_language: Option<bool>,
+      /// Language used for indented code blocks and fences without an info string, e.g. "rust".
+      /// Detection, when enabled, takes precedence
+      pub default_language: Option<String>,
+      /// Reuse the markup of code blocks that were already highlighted
+      pub highlight_cache: Option<HighlightCacheOptions>,
+      /// Code blocks longer than this many bytes are escaped without being highlighted or
+      /// detected. Unlimited by default
+      pub max_highlight_bytes: Option<u32>,
+      /// Milliseconds a single code block may spend being highlighted before it falls back to
+      /// escaped text. Unlimited by default
+      pub highlight_timeout_ms: Option<u32>,
+      /// What to do with HTML written in the markdown: "allow" it (the default), "escape" it so
+      /// it shows up as text, or "strip" it. Escaping or stripping also replaces `javascript:`
+      /// and other dangerous link and image URLs, for rendering markdown that can't be trusted
+      $(#[$raw_html])*
+      pub raw_html: Option<String>,
+      /// Words in the excerpt, 50 by default. A `<!-- more -->` marker ends it earlier
+      pub excerpt_words: Option<u32>,
+      /// Reading speed for the reading time, 200 words per minute by default
+      pub words_per_minute: Option<u32>,
+      /// Also return the document as plain text sections, one per heading, for search indexes
+      pub search_index: Option<SearchIndexOptions>,
+      /// Extra attributes for images, and `<figure>` wrappers for titled ones
+      pub images: Option<ImageOptions>,
+      /// Rules for rewriting links between documents, e.g. `../guide/intro.md#setup` to
+      /// `/guide/intro#setup`
+      pub links: Option<LinkOptions>,
+      /// Open links to other sites in a new tab, with `rel="noopener noreferrer nofollow"`
+      pub external_links: Option<ExternalLinkOptions>,
+      /// Render GitHub's `> [!NOTE]` blockquotes and `:::tip` containers as
+      /// `<aside class="callout callout-note">`. Off by default
+      pub callouts: Option<bool>,
+      /// Render `$...$`, `$$...$$` and ```` ```math ```` formulas as `math-inline` and
+      /// `math-display` elements for KaTeX or MathJax to typeset. Off by default
+      pub math: Option<bool>,
+      /// Languages of code blocks that hold diagrams, like "mermaid", "dot" or "plantuml". Their
+      /// source is emitted untouched in a container for a client side renderer, without the
+      /// code block markup
+      pub diagrams: Option<Vec<String>>,
     }
+  };
+}
+
+#[cfg(feature = "node")]
+markdown_options!(#[napi(ts_type = "'allow' | 'escape' | 'strip'")]);
+#[cfg(not(feature = "node"))]
+markdown_options!();
+
+/// How images are rendered. Every option is off unless turned on
+#[cfg_attr(feature = "node", napi(object))]
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageOptions {
+  /// Add `loading="lazy"`, so offscreen images load as they are scrolled to
+  pub lazy: Option<bool>,
+  /// Add `decoding="async"`
+  pub async_decoding: Option<bool>,
+  /// Render a titled image that is alone in its paragraph as a `<figure>`, with the title
+  /// as its `<figcaption>`
+  pub figures: Option<bool>,
+  /// Dimensions and sources of images by their src, after the link rules ran. The
+  /// `imageInfo` hook takes precedence
+  pub manifest: Option<HashMap<String, ImageInfo>>,
+}
+
+/// What is known about an image file. Setting the dimensions lets the browser reserve space
+/// for the image before it loads
+#[cfg_attr(feature = "node", napi(object))]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ImageInfo {
+  pub width: Option<u32>,
+  pub height: Option<u32>,
+  pub srcset: Option<String>,
+  pub sizes: Option<String>,
+}
 
-    ev
-  });
+/// What goes into the search sections
+#[cfg_attr(feature = "node", napi(object))]
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchIndexOptions {
+  /// Include the text of code blocks. Off by default
+  pub include_code: Option<bool>,
+}
 
-  html::write_html(Cursor::new(&mut output), stream).unwrap();
+/// Which links count as external and how they are marked
+#[cfg_attr(feature = "node", napi(object))]
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExternalLinkOptions {
+  /// The site's own origin, e.g. "https://example.com". Links to its host stay untouched,
+  /// links to any other http(s) host are external. Without it every http(s) link is treated
+  /// as external
+  pub site_origin: Option<String>,
+  /// Class added to external links, e.g. to show an icon beside them
+  pub class: Option<String>,
+}
 
-  let toc_html = generate_toc(&toc);
+/// How relative links and images are rewritten. URLs with a scheme, like `https:`, and
+/// fragments such as `#setup` are left alone
+#[cfg_attr(feature = "node", napi(object))]
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkOptions {
+  /// Prepended to every root relative link once the other rules ran, e.g. "/docs"
+  pub base_url: Option<String>,
+  /// Site path of the document being rendered, e.g. "/tutorial/start". Relative links are
+  /// resolved against it, and ones that climb above the root are reported as unresolved
+  pub path: Option<String>,
+  /// Drop the `.md` or `.markdown` extension from link paths. Off by default
+  pub strip_md_extension: Option<bool>,
+  /// Path prefixes to replace, e.g. {"/api/": "https://docs.rs/femark/latest/femark/"}.
+  /// The longest matching prefix wins
+  pub rewrites: Option<HashMap<String, String>>,
+}
 
-  match String::from_utf8(output) {
-    Ok(s) => Ok(HTMLOutput {
-      toc: toc_html,
-      content: s,
-    }),
-    Err(e) => Err(HighlightError::StringGenerationError(e.to_string()).into()),
-  }
+/// Keeps highlighted code blocks keyed by language and source, so repeated snippets are
+/// only highlighted once. Renderers with the same options share one cache for the life of
+/// the process
+#[cfg_attr(feature = "node", napi(object))]
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HighlightCacheOptions {
+  /// Number of blocks kept in memory, 1024 by default
+  pub capacity: Option<u32>,
+  /// Directory the cache is persisted to, so later runs can reuse it
+  pub directory: Option<String>,
 }
 
-#[derive(Debug, thiserror::Error)]
-enum HighlightError {
-  #[error("language not recognized")]
-  NoLang,
-  #[error("no highlighter for language")]
-  NoHighlighter,
-  #[error("could not build highlighter: {0}")]
-  CouldNotBuildHighlighter(String),
-  #[error("Could not generate utf8 String: {0}")]
-  StringGenerationError(String),
+/// Markdown syntax extensions. Tables, footnotes, strikethrough and task lists are on
+/// unless turned off, smart punctuation and heading attributes are off unless turned on
+#[cfg_attr(feature = "node", napi(object))]
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarkdownExtensions {
+  pub tables: Option<bool>,
+  pub footnotes: Option<bool>,
+  pub strikethrough: Option<bool>,
+  pub tasklists: Option<bool>,
+  pub smart_punctuation: Option<bool>,
+  /// `{#id .class}` after a heading sets its anchor and classes
+  pub heading_attributes: Option<bool>,
 }
 
-impl HighlightError {
-  fn benign(&self) -> bool {
-    matches!(self, Self::NoLang | Self::NoHighlighter)
-  }
+/// Everything a `Renderer` is configured with, besides its hooks
+#[cfg_attr(feature = "node", napi(object))]
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RendererConfig {
+  pub options: Option<MarkdownOptions>,
+  /// Name of a built in theme for the stylesheet: "basic", "github-light" or "github-dark"
+  pub theme: Option<String>,
+  /// Theme used when the reader prefers a dark color scheme
+  pub dark_theme: Option<String>,
+  /// Extra names for registered languages, e.g. {"rs": "rust"}
+  pub languages: Option<HashMap<String, String>>,
 }
 
-impl From<HighlightError> for napi::Error {
-  fn from(error: HighlightError) -> Self {
-    Self::new(napi::Status::GenericFailure, error.to_string())
+/// Renders markdown to HTML with the given options, syntax highlighting the code blocks and
+/// building a table of contents from the headings
+/// To render many documents with the same options, build a `Renderer` once instead
+pub fn render_markdown(
+  input: &str,
+  options: MarkdownOptions,
+) -> std::result::Result<HTMLOutput, Error> {
+  Renderer::from_options(options)?.render(input, &NoHooks)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+thread_local! {
+  // Highlighters hold parser state worth reusing, so every thread keeps one around
+  static HIGHLIGHTER: std::cell::RefCell<Highlighter> = std::cell::RefCell::new(Highlighter::new());
+}
+
+/// Bounds on the work spent highlighting a single code block
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Limits {
+  pub max_bytes: Option<usize>,
+  pub timeout: Option<Duration>,
+}
+
+impl Limits {
+  fn check_size(&self, key: &str, source: &str) -> std::result::Result<(), HighlightError> {
+    match self.max_bytes {
+      Some(limit) if source.len() > limit => Err(HighlightError::TooLarge {
+        lang: key.into(),
+        bytes: source.len(),
+        limit,
+      }),
+      _ => Ok(()),
+    }
   }
 }
 
+/// Writes `source` as HTML, highlighted with the grammar of `lang`. `key` is the name the
+/// language was looked up by
+#[cfg(not(target_arch = "wasm32"))]
 fn highlight_code(
   w: &mut dyn std::fmt::Write,
+  key: &str,
   source: &str,
   lang: &Option<&Lang>,
+  limits: Limits,
 ) -> std::result::Result<(), HighlightError> {
-  let lang = lang.ok_or(HighlightError::NoLang)?;
-  let conf = lang.conf.as_ref().ok_or(HighlightError::NoHighlighter)?;
-
-  let mut highlighter = Highlighter::new();
-  let highlights = highlighter
-    .highlight(conf, source.as_bytes(), None, |_| None)
-    .map_err(|e| HighlightError::CouldNotBuildHighlighter(format!("{:?}", e)))?;
-  for highlight in highlights {
-    let highlight = highlight.unwrap();
-    match highlight {
-      HighlightEvent::Source { start, end } => {
-        write_code_escaped(w, &source[start..end]).unwrap();
-      }
-      HighlightEvent::HighlightStart(Highlight(i)) => {
-        write!(w, r#"<i class=hh{}>"#, i).unwrap();
-      }
-      HighlightEvent::HighlightEnd => {
-        write!(w, r#"</i>"#).unwrap();
+  let conf = grammar_of(key, lang)?;
+  limits.check_size(key, source)?;
+  // Raised by the watchdog once the budget is spent, which makes tree-sitter give up
+  let cancellation_flag = limits.timeout.map(watchdog::cancel_after);
+  let failed = |e: tree_sitter_highlight::Error| match (e, limits.timeout) {
+    (tree_sitter_highlight::Error::Cancelled, Some(budget)) => HighlightError::TimedOut {
+      lang: key.into(),
+      budget_ms: budget.as_millis(),
+    },
+    (e, _) => HighlightError::Highlighter {
+      lang: key.into(),
+      message: format!("{:?}", e),
+    },
+  };
+
+  HIGHLIGHTER.with(|highlighter| {
+    let mut highlighter = highlighter.borrow_mut();
+    let mut write_highlights = || {
+      let highlights = highlighter
+        .highlight(
+          conf,
+          source.as_bytes(),
+          cancellation_flag.as_deref(),
+          |_| None,
+        )
+        .map_err(failed)?;
+      for highlight in highlights {
+        match highlight.map_err(failed)? {
+          HighlightEvent::Source { start, end } => {
+            write_code_escaped(w, &source[start..end])?;
+          }
+          HighlightEvent::HighlightStart(Highlight(i)) => {
+            write!(w, r#"<i class=hh{}>"#, i)?;
+          }
+          HighlightEvent::HighlightEnd => {
+            write!(w, r#"</i>"#)?;
+          }
+        }
       }
+      Ok(())
+    };
+
+    let result = write_highlights();
+    if result.is_err() {
+      // Otherwise the next block would resume the parse that was cancelled
+      highlighter.parser().reset();
     }
-  }
+    result
+  })
+}
 
-  Ok(())
+#[cfg(target_arch = "wasm32")]
+fn highlight_code(
+  _w: &mut dyn std::fmt::Write,
+  key: &str,
+  _source: &str,
+  lang: &Option<&Lang>,
+  _limits: Limits,
+) -> std::result::Result<(), HighlightError> {
+  match *grammar_of(key, lang)? {}
 }
 
-fn write_code_escaped(w: &mut dyn std::fmt::Write, input: &str) -> Result<()> {
+/// The grammar to highlight a block with, or why there is none
+fn grammar_of<'l>(
+  key: &str,
+  lang: &Option<&'l Lang>,
+) -> std::result::Result<&'l HighlightConfiguration, HighlightError> {
+  let lang = lang.ok_or_else(|| HighlightError::UnknownLanguage(key.into()))?;
+  match (&lang.conf, &lang.grammar_error) {
+    (Some(conf), _) => Ok(conf),
+    (None, Some(message)) => Err(HighlightError::BrokenGrammar {
+      lang: key.into(),
+      message: message.clone(),
+    }),
+    (None, None) => Err(HighlightError::NoGrammar(key.into())),
+  }
+}
+
+fn write_code_escaped(w: &mut dyn std::fmt::Write, input: &str) -> std::fmt::Result {
   let mut start: Option<usize> = None;
 
   for (i, c) in input.char_indices() {
@@ -567,4 +590,34 @@ mod tests {
       "ParseResult&lt;&amp;str&gt; Or Result&lt;Vec&lt;_&gt;&gt; &amp;&amp; false"
     );
   }
+
+  #[test]
+  fn test_detect_language_for_unlabeled_blocks() {
+    let input = "    fn main() {\n        println!(\"hi\");\n    }\n";
+    let plain = render_markdown(input, Default::default()).unwrap();
+    assert!(plain.content.contains(r#"data-lang="""#));
+    assert!(!plain.content.contains("<i class=hh"));
+
+    let options = MarkdownOptions {
+      detect_language: Some(true),
+      ..Default::default()
+    };
+    let detected = render_markdown(input, options).unwrap();
+    assert!(detected.content.contains(r#"data-lang="rust""#));
+    assert!(detected.content.contains("<i class=hh"));
+  }
+
+  #[test]
+  fn test_indented_code_uses_default_language() {
+    let input = "Some code:\n\n    let x = 1 < 2;\n";
+    let options = MarkdownOptions {
+      default_language: Some("javascript".into()),
+      ..Default::default()
+    };
+    let output = render_markdown(input, options).unwrap();
+    assert!(output.content.contains(r#"<div class="code-block">"#));
+    assert!(output.content.contains(r#"data-lang="javascript""#));
+    assert!(output.content.contains("&lt;"));
+    assert!(!output.content.contains("<pre><code>"));
+  }
 }
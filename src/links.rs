@@ -0,0 +1,258 @@
+use crate::{Error, ExternalLinkOptions, LinkOptions};
+use pulldown_cmark::escape::{escape_href, escape_html};
+use std::borrow::Cow;
+
+/// What became of a link
+#[derive(Debug, PartialEq, Eq)]
+pub enum ResolvedLink {
+  Url(String),
+  /// The link points at something that doesn't exist, like a page above the site root
+  Unresolved,
+}
+
+/// The link rewriting rules of a renderer, applied to links and images without a scheme
+#[derive(Default)]
+pub(crate) struct LinkRules {
+  base_url: Option<String>,
+  path: Option<String>,
+  strip_md_extension: bool,
+  // Longest prefix first, so the most specific rewrite wins
+  rewrites: Vec<(String, String)>,
+}
+
+impl LinkRules {
+  pub(crate) fn new(options: &LinkOptions) -> Self {
+    let mut rewrites: Vec<_> = options
+      .rewrites
+      .clone()
+      .unwrap_or_default()
+      .into_iter()
+      .collect();
+    rewrites.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then(a.cmp(b)));
+    Self {
+      base_url: options
+        .base_url
+        .as_ref()
+        .map(|base| base.trim_end_matches('/').to_string()),
+      path: options.path.clone(),
+      strip_md_extension: options.strip_md_extension.unwrap_or(false),
+      rewrites,
+    }
+  }
+
+  /// Rewrites `url` by the rules, or returns None when they leave it as it is
+  pub(crate) fn resolve(&self, url: &str) -> Option<ResolvedLink> {
+    if url.is_empty() || url.starts_with('#') || url.starts_with("//") || has_scheme(url) {
+      return None;
+    }
+
+    let (path, suffix) = url.split_at(url.find(['?', '#']).unwrap_or(url.len()));
+    let mut path = Cow::Borrowed(path);
+    if self.strip_md_extension {
+      if let Some(stripped) = [".md", ".markdown"]
+        .iter()
+        .find_map(|extension| path.strip_suffix(extension))
+      {
+        path = Cow::Owned(stripped.to_string());
+      }
+    }
+    if !path.starts_with('/') {
+      if let Some(document) = &self.path {
+        match join(document, &path) {
+          Some(joined) => path = Cow::Owned(joined),
+          None => return Some(ResolvedLink::Unresolved),
+        }
+      }
+    }
+    if let Some((prefix, replacement)) = self
+      .rewrites
+      .iter()
+      .find(|(prefix, _)| path.starts_with(prefix.as_str()))
+    {
+      path = Cow::Owned(format!("{replacement}{}", &path[prefix.len()..]));
+    }
+    if let Some(base_url) = &self.base_url {
+      if path.starts_with('/') {
+        path = Cow::Owned(format!("{base_url}{path}"));
+      }
+    }
+
+    match path {
+      Cow::Borrowed(_) => None,
+      Cow::Owned(path) => Some(ResolvedLink::Url(path + suffix)),
+    }
+  }
+}
+
+/// Decorates links to other sites, so they open in a new tab without handing the page over
+/// to them
+pub(crate) struct ExternalLinks {
+  // Links to this host are internal. Without one, every http(s) link is external
+  site_host: Option<String>,
+  class: Option<String>,
+}
+
+impl ExternalLinks {
+  pub(crate) fn new(options: &ExternalLinkOptions) -> Result<Self, Error> {
+    let site_host = options
+      .site_origin
+      .as_deref()
+      .map(|origin| {
+        host(origin).map(str::to_ascii_lowercase).ok_or_else(|| {
+          Error::InvalidConfig(format!(
+            "siteOrigin must be an http or https URL, got {origin:?}"
+          ))
+        })
+      })
+      .transpose()?;
+    Ok(Self {
+      site_host,
+      class: options.class.clone(),
+    })
+  }
+
+  pub(crate) fn is_external(&self, url: &str) -> bool {
+    match (host(url), &self.site_host) {
+      (Some(host), Some(site_host)) => !host.eq_ignore_ascii_case(site_host),
+      (Some(_), None) => true,
+      (None, _) => false,
+    }
+  }
+
+  /// The opening tag of an external link, written like pulldown-cmark's own
+  pub(crate) fn start_tag(&self, dest: &str, title: &str) -> String {
+    let mut tag = String::from("<a href=\"");
+    escape_href(&mut tag, dest).ok();
+    if !title.is_empty() {
+      tag.push_str("\" title=\"");
+      escape_html(&mut tag, title).ok();
+    }
+    if let Some(class) = &self.class {
+      tag.push_str("\" class=\"");
+      escape_html(&mut tag, class).ok();
+    }
+    tag.push_str(r#"" rel="noopener noreferrer nofollow" target="_blank">"#);
+    tag
+  }
+}
+
+/// The host, with its port, of an http(s) or protocol relative URL
+fn host(url: &str) -> Option<&str> {
+  let lowercase = |n: usize| url.get(..n).map(str::to_ascii_lowercase);
+  let rest = if lowercase(7).as_deref() == Some("http://") {
+    &url[7..]
+  } else if lowercase(8).as_deref() == Some("https://") {
+    &url[8..]
+  } else {
+    url.strip_prefix("//")?
+  };
+  let authority = &rest[..rest.find(['/', '?', '#']).unwrap_or(rest.len())];
+  let host = authority
+    .rsplit_once('@')
+    .map_or(authority, |(_, host)| host);
+  (!host.is_empty()).then_some(host)
+}
+
+/// Whether a URL starts with a scheme, like "https:" or "mailto:"
+fn has_scheme(url: &str) -> bool {
+  match url.split_once(':') {
+    Some((scheme, _)) => {
+      scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+          .chars()
+          .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+    }
+    None => false,
+  }
+}
+
+/// Resolves a relative path against the path of the document it appears in. Returns None
+/// when it climbs above the root
+fn join(document: &str, relative: &str) -> Option<String> {
+  let mut segments: Vec<&str> = document.split('/').filter(|s| !s.is_empty()).collect();
+  // "/guide/intro" lives in "/guide/", while "/guide/" is a directory itself
+  if !document.ends_with('/') {
+    segments.pop();
+  }
+  for segment in relative.split('/') {
+    match segment {
+      "" | "." => {}
+      ".." => {
+        segments.pop()?;
+      }
+      segment => segments.push(segment),
+    }
+  }
+
+  let mut joined = format!("/{}", segments.join("/"));
+  if relative.ends_with('/') && !joined.ends_with('/') {
+    joined.push('/');
+  }
+  Some(joined)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::collections::HashMap;
+
+  #[test]
+  fn test_link_rules() {
+    let rules = LinkRules::new(&LinkOptions {
+      base_url: Some("https://example.com/docs/".into()),
+      path: Some("/tutorial/start".into()),
+      strip_md_extension: Some(true),
+      rewrites: Some(HashMap::from([
+        ("/api/".into(), "/reference/".into()),
+        ("/api/v1/".into(), "/legacy/".into()),
+      ])),
+    });
+    let url = |url: &str| rules.resolve(url);
+    let resolved = |to: &str| Some(ResolvedLink::Url(to.into()));
+
+    assert_eq!(
+      url("../guide/intro.md#setup"),
+      resolved("https://example.com/docs/guide/intro#setup")
+    );
+    assert_eq!(
+      url("next.md?x=1"),
+      resolved("https://example.com/docs/tutorial/next?x=1")
+    );
+    assert_eq!(
+      url("/api/v1/render"),
+      resolved("https://example.com/docs/legacy/render")
+    );
+    assert_eq!(
+      url("/api/render"),
+      resolved("https://example.com/docs/reference/render")
+    );
+    assert_eq!(url("../../outside.md"), Some(ResolvedLink::Unresolved));
+    assert_eq!(url("https://rust-lang.org/x.md"), None);
+    assert_eq!(url("mailto:someone@example.com"), None);
+    assert_eq!(url("#setup"), None);
+
+    // Without rules, links are left alone
+    assert_eq!(LinkRules::default().resolve("../guide/intro.md"), None);
+  }
+
+  #[test]
+  fn test_external_links() {
+    let links = ExternalLinks::new(&ExternalLinkOptions {
+      site_origin: Some("https://Example.com".into()),
+      class: None,
+    })
+    .unwrap();
+    assert!(links.is_external("https://rust-lang.org/learn"));
+    assert!(links.is_external("//cdn.example.org/x.js"));
+    assert!(!links.is_external("HTTP://example.com/about"));
+    assert!(!links.is_external("https://user@example.com"));
+    assert!(!links.is_external("/about"));
+    assert!(!links.is_external("mailto:someone@example.org"));
+
+    assert!(ExternalLinks::new(&ExternalLinkOptions {
+      site_origin: Some("example.com".into()),
+      class: None,
+    })
+    .is_err());
+  }
+}
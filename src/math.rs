@@ -0,0 +1,497 @@
+use pulldown_cmark::escape::escape_html;
+use pulldown_cmark::{CowStr, Event, LinkType, Tag};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+
+// Formulas are swapped for `OPEN index CLOSE` before parsing, characters from the private
+// use area that markdown gives no meaning to
+const OPEN: char = '\u{E000}';
+const CLOSE: char = '\u{E001}';
+
+struct Formula {
+  source: String,
+  display: bool,
+  // Line breaks the formula took in, along with the container prefixes after them
+  lines: usize,
+}
+
+/// The `$...$` and `$$...$$` formulas taken out of a document, so markdown never sees the
+/// underscores and asterisks inside them
+#[derive(Default)]
+pub(crate) struct Formulas(Vec<Formula>);
+
+/// Replaces the formulas in the prose of a document with placeholders. Code blocks and code
+/// spans are left alone, and formulas don't span paragraphs
+pub(crate) fn extract(input: &str) -> (Cow<'_, str>, Formulas) {
+  let mut formulas = Formulas::default();
+  if !input.contains('$') {
+    return (Cow::Borrowed(input), formulas);
+  }
+
+  let mut out = String::with_capacity(input.len());
+  // Lines of the paragraph so far, split into their blockquote markers and the rest
+  let mut prose: Vec<(&str, &str)> = Vec::new();
+  let mut fence: Option<(char, usize)> = None;
+  let mut after_blank = true;
+  // The indentation of the list item's content, when in one
+  let mut list_indent = 0;
+  for line in input.split_inclusive('\n') {
+    let (prefix, content) = split_quote(line);
+    let trimmed = content.trim_start();
+    let indent = columns(&content[..content.len() - trimmed.len()]);
+    let blank = trimmed.is_empty();
+    if fence.is_none() && !blank {
+      if let Some(width) = list_item(content) {
+        list_indent = width;
+      } else if after_blank && indent < list_indent {
+        list_indent = 0;
+      }
+    }
+
+    let marker = trimmed.chars().next().filter(|c| matches!(c, '`' | '~'));
+    let len = marker.map_or(0, |c| trimmed.chars().take_while(|&x| x == c).count());
+    let code = match (fence, marker) {
+      (Some((open, open_len)), Some(c)) if open == c && len >= open_len => {
+        fence = None;
+        true
+      }
+      (Some(_), _) => true,
+      (None, Some(c)) if indent < list_indent + 4 && len >= 3 => {
+        fence = Some((c, len));
+        true
+      }
+      // Indented code can't interrupt a paragraph
+      _ => after_blank && indent >= list_indent + 4 && !blank,
+    };
+    if code || blank {
+      formulas.replace(&prose, &mut out);
+      prose.clear();
+      out.push_str(line);
+    } else {
+      prose.push((prefix, content));
+    }
+    after_blank = blank || code;
+  }
+  formulas.replace(&prose, &mut out);
+  (Cow::Owned(out), formulas)
+}
+
+/// Splits the `>` markers of blockquotes off a line
+fn split_quote(line: &str) -> (&str, &str) {
+  let mut rest = line;
+  loop {
+    let trimmed = rest.trim_start_matches(' ');
+    match trimmed.strip_prefix('>') {
+      Some(after) if rest.len() - trimmed.len() < 4 => {
+        rest = after.strip_prefix(' ').unwrap_or(after);
+      }
+      _ => break,
+    }
+  }
+  line.split_at(line.len() - rest.len())
+}
+
+/// The indentation of a list item's content, if the line starts one
+fn list_item(line: &str) -> Option<usize> {
+  let trimmed = line.trim_start_matches(' ');
+  let indent = line.len() - trimmed.len();
+  let digits = trimmed.chars().take_while(char::is_ascii_digit).count();
+  let marker = match trimmed[digits..].chars().next()? {
+    '-' | '+' | '*' if digits == 0 => 1,
+    '.' | ')' if (1..10).contains(&digits) => digits + 1,
+    _ => return None,
+  };
+  let after = &trimmed[marker..];
+  let spaces = after.chars().take_while(|&c| c == ' ').count();
+  match after[spaces..].trim_end().is_empty() {
+    true => Some(indent + marker + 1),
+    false if spaces == 0 => None,
+    // Content indented further than that starts an indented code block of its own
+    false => Some(indent + marker + if spaces > 4 { 1 } else { spaces }),
+  }
+}
+
+/// The width of leading whitespace, with tabs stopping every 4 columns
+fn columns(whitespace: &str) -> usize {
+  whitespace.chars().fold(0, |col, c| {
+    if c == '\t' {
+      col + 4 - col % 4
+    } else {
+      col + 1
+    }
+  })
+}
+
+impl Formulas {
+  /// Copies a paragraph of prose to `out`, with placeholders for its formulas. Formulas are
+  /// read without the blockquote markers of their lines
+  fn replace(&mut self, prose: &[(&str, &str)], out: &mut String) {
+    let text: String = prose.iter().map(|(_, content)| *content).collect();
+    let mut replaced = String::with_capacity(text.len());
+    self.replace_text(&text, &mut replaced);
+
+    let mut prefixes = prose.iter().map(|(prefix, _)| *prefix);
+    for line in replaced.split_inclusive('\n') {
+      out.push_str(prefixes.next().unwrap_or_default());
+      out.push_str(line);
+      // The lines a formula took in are gone, along with their markers
+      self.split(line, |piece| {
+        if let Piece::Formula(formula) = piece {
+          prefixes.by_ref().take(formula.lines).for_each(drop);
+        }
+      });
+    }
+  }
+
+  fn replace_text(&mut self, prose: &str, out: &mut String) {
+    let mut rest = prose;
+    while let Some(i) = rest.find(['\\', '`', '$']) {
+      out.push_str(&rest[..i]);
+      rest = &rest[i..];
+      let taken = match rest.as_bytes()[0] {
+        // Escaped characters, including `\$`, are copied as they are
+        b'\\' => rest.chars().take(2).map(char::len_utf8).sum(),
+        b'`' => code_span(rest),
+        _ => match self.formula(rest) {
+          Some((taken, formula)) => {
+            out.push(OPEN);
+            out.push_str(&self.0.len().to_string());
+            out.push(CLOSE);
+            self.0.push(formula);
+            rest = &rest[taken..];
+            continue;
+          }
+          None => rest.chars().take_while(|&c| c == '$').count(),
+        },
+      };
+      out.push_str(&rest[..taken]);
+      rest = &rest[taken..];
+    }
+    out.push_str(rest);
+  }
+
+  /// Reads the formula at the start of `text`, returning how many bytes it takes up
+  fn formula(&self, text: &str) -> Option<(usize, Formula)> {
+    if let Some(body) = text.strip_prefix("$$") {
+      let end = closing(body, |i| body[i..].starts_with("$$"))?;
+      let source = body[..end].trim();
+      return (!source.is_empty()).then(|| {
+        let formula = Formula {
+          source: source.into(),
+          display: true,
+          lines: body[..end].matches('\n').count(),
+        };
+        (end + 4, formula)
+      });
+    }
+
+    // Like pandoc, `$` only opens before and closes after something other than whitespace,
+    // and never closes before a digit, so prices like $5 and $10 stay text
+    let body = &text[1..];
+    if body.starts_with(char::is_whitespace) {
+      return None;
+    }
+    let end = closing(body, |i| {
+      body[i..].starts_with('$')
+        && !body[..i].ends_with(char::is_whitespace)
+        && !body[i + 1..].starts_with(|c: char| c.is_ascii_digit())
+    })?;
+    (end > 0).then(|| {
+      let formula = Formula {
+        source: body[..end].into(),
+        display: false,
+        lines: body[..end].matches('\n').count(),
+      };
+      (end + 2, formula)
+    })
+  }
+
+  /// Replaces the placeholders in a text with the formulas' source, for plain text uses
+  /// like slugs and word counts. HTML and code get the formulas as they were written
+  pub(crate) fn restore<'e, 'a>(&self, ev: &'e Event<'a>) -> Cow<'e, Event<'a>> {
+    self.restore_event(ev, false)
+  }
+
+  fn restore_event<'e, 'a>(&self, ev: &'e Event<'a>, written: bool) -> Cow<'e, Event<'a>> {
+    match ev {
+      _ if self.0.is_empty() => Cow::Borrowed(ev),
+      Event::Text(text) if text.contains(OPEN) => {
+        Cow::Owned(Event::Text(self.restore_text(text, written).into()))
+      }
+      Event::Code(text) if text.contains(OPEN) => {
+        Cow::Owned(Event::Code(self.restore_text(text, true).into()))
+      }
+      Event::Html(html) if html.contains(OPEN) => {
+        Cow::Owned(Event::Html(self.restore_text(html, true).into()))
+      }
+      ev => Cow::Borrowed(ev),
+    }
+  }
+
+  /// The text with its formulas' source, and their delimiters when `written`
+  fn restore_text(&self, text: &str, written: bool) -> String {
+    let mut out = String::with_capacity(text.len());
+    self.split(text, |piece| match piece {
+      Piece::Text(text) => out.push_str(text),
+      Piece::Formula(formula) => {
+        let delimiter = match (written, formula.display) {
+          (false, _) => "",
+          (true, true) => "$$",
+          (true, false) => "$",
+        };
+        out.push_str(delimiter);
+        out.push_str(&formula.source);
+        out.push_str(delimiter);
+      }
+    });
+    out
+  }
+
+  fn restore_str<'a>(&self, text: CowStr<'a>) -> CowStr<'a> {
+    match text.contains(OPEN) {
+      true => self.restore_text(&text, true).into(),
+      false => text,
+    }
+  }
+
+  fn restore_tag<'a>(&self, tag: Tag<'a>) -> Tag<'a> {
+    match tag {
+      Tag::Link(kind, dest, title) => {
+        Tag::Link(kind, self.restore_str(dest), self.restore_str(title))
+      }
+      Tag::Image(kind, dest, title) => {
+        Tag::Image(kind, self.restore_str(dest), self.restore_str(title))
+      }
+      tag => tag,
+    }
+  }
+
+  /// Puts formulas back as written where markdown takes text literally: the targets and
+  /// titles of links and images, the text of autolinks and the alt text of images
+  pub(crate) fn literal<'f, 'a>(
+    &'f self,
+    events: impl Iterator<Item = Event<'a>> + 'f,
+  ) -> impl Iterator<Item = Event<'a>> + 'f {
+    let literal = |tag: &Tag| {
+      matches!(
+        tag,
+        Tag::Image(..) | Tag::Link(LinkType::Autolink | LinkType::Email, ..)
+      )
+    };
+    let mut depth = 0;
+    events.map(move |ev| match ev {
+      _ if self.0.is_empty() => ev,
+      Event::Start(tag) => {
+        depth += literal(&tag) as usize;
+        Event::Start(self.restore_tag(tag))
+      }
+      Event::End(tag) => {
+        depth -= literal(&tag) as usize;
+        Event::End(self.restore_tag(tag))
+      }
+      ev if depth > 0 => self.restore_event(&ev, true).into_owned(),
+      ev => ev,
+    })
+  }
+
+  fn split<'t>(&'t self, mut text: &'t str, mut f: impl FnMut(Piece<'t>)) {
+    while let Some(start) = text.find(OPEN) {
+      let after = &text[start + OPEN.len_utf8()..];
+      let formula = after
+        .find(CLOSE)
+        .and_then(|end| Some((end, self.0.get(after[..end].parse::<usize>().ok()?)?)));
+      match formula {
+        Some((end, formula)) => {
+          f(Piece::Text(&text[..start]));
+          f(Piece::Formula(formula));
+          text = &after[end + CLOSE.len_utf8()..];
+        }
+        // Not one of ours, so it stays
+        None => {
+          f(Piece::Text(&text[..start + OPEN.len_utf8()]));
+          text = after;
+        }
+      }
+    }
+    f(Piece::Text(text));
+  }
+}
+
+enum Piece<'t> {
+  Text(&'t str),
+  Formula(&'t Formula),
+}
+
+/// Where the first unescaped position in `body` that `closes` accepts is. Formulas don't
+/// take in code spans, so one coming first means there's no closing
+fn closing(body: &str, closes: impl Fn(usize) -> bool) -> Option<usize> {
+  let mut i = 0;
+  while let Some(found) = body[i..].find(['\\', '`', '$']) {
+    i += found;
+    i += match body.as_bytes()[i] {
+      b'\\' => body[i..].chars().take(2).map(char::len_utf8).sum(),
+      b'`' => {
+        let ticks = body[i..].chars().take_while(|&c| c == '`').count();
+        match code_span(&body[i..]) {
+          // Backticks that never close are just text
+          taken if taken == ticks => ticks,
+          _ => return None,
+        }
+      }
+      _ if closes(i) => return Some(i),
+      _ => 1,
+    };
+  }
+  None
+}
+
+/// How many bytes the code span at the start of `text` takes up, or just its opening
+/// backticks when it's never closed
+fn code_span(text: &str) -> usize {
+  let ticks = text.chars().take_while(|&c| c == '`').count();
+  let mut rest = &text[ticks..];
+  let mut offset = ticks;
+  while let Some(i) = rest.find('`') {
+    let run = rest[i..].chars().take_while(|&c| c == '`').count();
+    if run == ticks {
+      return offset + i + run;
+    }
+    offset += i + run;
+    rest = &rest[i + run..];
+  }
+  ticks
+}
+
+/// Markup for a formula, left for KaTeX or MathJax to typeset in the browser
+pub(crate) fn markup(source: &str, display: bool) -> String {
+  let mut out = String::with_capacity(source.len() + 48);
+  out.push_str(match display {
+    true => r#"<span class="math math-display">"#,
+    false => r#"<span class="math math-inline">"#,
+  });
+  escape_html(&mut out, source).ok();
+  out.push_str("</span>");
+  out
+}
+
+/// Puts the markup of the formulas back where their placeholders ended up
+pub(crate) struct Math<'f, 'a, I> {
+  inner: I,
+  formulas: &'f Formulas,
+  ready: VecDeque<Event<'a>>,
+}
+
+impl<'f, 'a, I: Iterator<Item = Event<'a>>> Math<'f, 'a, I> {
+  pub(crate) fn new(inner: I, formulas: &'f Formulas) -> Self {
+    Self {
+      inner,
+      formulas,
+      ready: VecDeque::new(),
+    }
+  }
+}
+
+impl<'f, 'a, I: Iterator<Item = Event<'a>>> Iterator for Math<'f, 'a, I> {
+  type Item = Event<'a>;
+
+  fn next(&mut self) -> Option<Event<'a>> {
+    if let Some(ev) = self.ready.pop_front() {
+      return Some(ev);
+    }
+    let ev = self.inner.next()?;
+    match &ev {
+      Event::Text(text) if !self.formulas.0.is_empty() && text.contains(OPEN) => {
+        let ready = &mut self.ready;
+        self.formulas.split(text, |piece| match piece {
+          Piece::Text("") => {}
+          Piece::Text(text) => ready.push_back(Event::Text(text.to_string().into())),
+          Piece::Formula(formula) => {
+            ready.push_back(Event::Html(markup(&formula.source, formula.display).into()))
+          }
+        });
+        self.ready.pop_front()
+      }
+      // Formulas in HTML and code are shown as they were written
+      _ => Some(self.formulas.restore_event(&ev, true).into_owned()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pulldown_cmark::{html, Parser};
+
+  fn render(input: &str) -> String {
+    let (input, formulas) = extract(input);
+    let mut out = String::new();
+    let events = formulas.literal(Parser::new(&input));
+    html::push_html(&mut out, Math::new(events, &formulas));
+    out
+  }
+
+  #[test]
+  fn test_formulas_keep_their_markdown_characters() {
+    assert_eq!(
+      render("Let $a_1 * b_2 < c$ and *this*.\n"),
+      "<p>Let <span class=\"math math-inline\">a_1 * b_2 &lt; c</span> and <em>this</em>.</p>\n"
+    );
+    assert_eq!(
+      render("$$\n\\sum_{i=1}^n x_i^*\n$$\n"),
+      "<p><span class=\"math math-display\">\\sum_{i=1}^n x_i^*</span></p>\n"
+    );
+  }
+
+  #[test]
+  fn test_dollars_that_are_not_math() {
+    assert_eq!(
+      render("It costs $5 or $10.\n"),
+      "<p>It costs $5 or $10.</p>\n"
+    );
+    assert_eq!(render("Not \\$x$ math\n"), "<p>Not $x$ math</p>\n");
+    assert_eq!(render("`$x_1$`\n"), "<p><code>$x_1$</code></p>\n");
+    assert_eq!(
+      render("```\n$x_1$\n```\n"),
+      "<pre><code>$x_1$\n</code></pre>\n"
+    );
+    // Formulas end with their paragraph
+    assert_eq!(render("$a\n\nb$\n"), "<p>$a</p>\n<p>b$</p>\n");
+  }
+
+  #[test]
+  fn test_links_keep_their_dollars() {
+    assert_eq!(
+      render("[the API](https://api.example.com/items?$filter=x&$top=5 \"costs $x$\")\n"),
+      "<p><a href=\"https://api.example.com/items?$filter=x&amp;$top=5\" title=\"costs $x$\">the API</a></p>\n"
+    );
+    assert_eq!(
+      render("<https://x.com/?$a$>\n"),
+      "<p><a href=\"https://x.com/?$a$\">https://x.com/?$a$</a></p>\n"
+    );
+    assert_eq!(
+      render("![$x_1$](/a.png \"costs $x$\")\n\n[r]\n\n[r]: /r?$a$\n"),
+      "<p><img src=\"/a.png\" alt=\"$x_1$\" title=\"costs $x$\" /></p>\n<p><a href=\"/r?$a$\">r</a></p>\n"
+    );
+  }
+
+  #[test]
+  fn test_formulas_in_containers() {
+    assert_eq!(
+      render("> $$\n> x_1\n> $$\n"),
+      "<blockquote>\n<p><span class=\"math math-display\">x_1</span></p>\n</blockquote>\n"
+    );
+    assert_eq!(
+      render("> Quoted $a_b$\n> ```\n> $c_d$\n> ```\n"),
+      "<blockquote>\n<p>Quoted <span class=\"math math-inline\">a_b</span></p>\n<pre><code>$c_d$\n</code></pre>\n</blockquote>\n"
+    );
+    assert_eq!(
+      render("- item\n\n    with $a_b$ math\n"),
+      "<ul>\n<li>\n<p>item</p>\n<p>with <span class=\"math math-inline\">a_b</span> math</p>\n</li>\n</ul>\n"
+    );
+    // Still code, four columns past the item's content
+    assert_eq!(
+      render("- item\n\n        $a_b$\n"),
+      "<ul>\n<li>\n<p>item</p>\n<pre><code>  $a_b$\n</code></pre>\n</li>\n</ul>\n"
+    );
+  }
+}
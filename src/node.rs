@@ -0,0 +1,456 @@
+//! Node.js bindings, exposed through napi-rs
+use crate::{
+  Error, HTMLOutput, Hooks, ImageInfo, MarkdownOptions, NoHooks, Renderer, RendererConfig,
+  ResolvedLink,
+};
+use napi::bindgen_prelude::FromNapiValue;
+use napi::bindgen_prelude::{AsyncTask, JsError, ObjectFinalize};
+use napi::NapiRaw;
+use napi::Task;
+use napi::{Env, JsFunction, JsUnknown, Ref, ValueType};
+use std::{cell::RefCell, io};
+
+/// JavaScript callbacks that customize rendering. Returning undefined from a hook keeps
+/// femark's default behavior
+#[napi(object, object_to_js = false)]
+#[derive(Default)]
+pub struct RendererHooks {
+  /// (text: string) => string, builds the anchor slug of a heading
+  #[napi(ts_type = "(text: string) => string | undefined | null")]
+  pub slugify: Option<JsFunction>,
+  /// (lang: string, source: string) => string, highlights code in languages femark has no
+  /// grammar for. The returned HTML is not escaped
+  #[napi(ts_type = "(lang: string, source: string) => string | undefined | null")]
+  pub highlight: Option<JsFunction>,
+  /// (url: string) => string | false, rewrites the target of a link or image. Returning
+  /// false reports the target as unresolved, undefined leaves it to the link rules
+  #[napi(ts_type = "(url: string) => string | false | undefined | null")]
+  pub resolve_link: Option<JsFunction>,
+  /// (src: string) => ImageInfo, supplies the width, height and srcset of an image. Only
+  /// called when the images option is set
+  #[napi(ts_type = "(src: string) => ImageInfo | undefined | null")]
+  pub image_info: Option<JsFunction>,
+}
+
+/// Processes markdown to html and syntax highlights the code blocks
+/// Takes in a string and returns an object containing the content HTML and the toc html
+/// Input: string, optional options object
+/// Output: {toc: string, content: string}
+#[napi]
+pub fn process_markdown_to_html(
+  env: Env,
+  input: String,
+  options: Option<MarkdownOptions>,
+) -> Result<HTMLOutput, napi::bindgen_prelude::Error> {
+  render(&input, options).map_err(|e| js_error(&env, e))
+}
+
+fn render(input: &str, options: Option<MarkdownOptions>) -> Result<HTMLOutput, Error> {
+  Renderer::from_options(options.unwrap_or_default())?.render(input, &NoHooks)
+}
+
+/// Renders a document on the libuv threadpool, so highlighting doesn't block the JS thread
+pub struct RenderTask {
+  input: String,
+  options: Option<MarkdownOptions>,
+  // Kept to be thrown with its code once back on the JS thread
+  error: Option<Error>,
+}
+
+impl Task for RenderTask {
+  type Output = HTMLOutput;
+  type JsValue = HTMLOutput;
+
+  fn compute(&mut self) -> Result<Self::Output, napi::bindgen_prelude::Error> {
+    render(&self.input, self.options.take()).map_err(|e| {
+      let reason = e.to_string();
+      self.error = Some(e);
+      napi::Error::new(napi::Status::GenericFailure, reason)
+    })
+  }
+
+  fn resolve(
+    &mut self,
+    _env: Env,
+    output: Self::Output,
+  ) -> Result<Self::JsValue, napi::bindgen_prelude::Error> {
+    Ok(output)
+  }
+
+  fn reject(
+    &mut self,
+    env: Env,
+    err: napi::bindgen_prelude::Error,
+  ) -> Result<Self::JsValue, napi::bindgen_prelude::Error> {
+    Err(self.error.take().map_or(err, |e| js_error(&env, e)))
+  }
+}
+
+/// Processes markdown to html like processMarkdownToHtml, but on a worker thread
+/// Input: string, optional options object
+/// Output: Promise<{toc: string, content: string}>
+#[napi]
+pub fn process_markdown_to_html_async(
+  input: String,
+  options: Option<MarkdownOptions>,
+) -> AsyncTask<RenderTask> {
+  AsyncTask::new(RenderTask {
+    input,
+    options,
+    error: None,
+  })
+}
+
+/// Processes markdown to html, handing the HTML to onChunk piece by piece as it is rendered
+/// instead of returning it all at once. Chunks arrive in order and are roughly 16KiB each
+/// Input: string, (chunk: string) => void, optional options object
+/// Output: {toc: string, content: ""}, returned once the last chunk was emitted
+#[napi]
+pub fn process_markdown_to_html_stream(
+  env: Env,
+  input: String,
+  #[napi(ts_arg_type = "(chunk: string) => void")] on_chunk: JsFunction,
+  options: Option<MarkdownOptions>,
+) -> Result<HTMLOutput, napi::bindgen_prelude::Error> {
+  let renderer =
+    Renderer::from_options(options.unwrap_or_default()).map_err(|e| js_error(&env, e))?;
+  let mut callback_error = None;
+  let mut writer = ChunkWriter::new(|chunk: &str| {
+    on_chunk
+      .call1::<_, JsUnknown>(chunk)
+      .map(|_| ())
+      .map_err(|e| {
+        let message = e.reason.clone();
+        callback_error = Some(e);
+        io::Error::other(message)
+      })
+  });
+  let output = renderer
+    .render_to_writer(&input, &NoHooks, &mut writer)
+    .and_then(|output| Ok(writer.finish().map(|_| output)?));
+  drop(writer);
+  if let Some(e) = callback_error {
+    return Err(e);
+  }
+  output.map_err(|e| js_error(&env, e))
+}
+
+/// Buffers writes into chunks of about CHUNK_SIZE bytes before passing them on
+struct ChunkWriter<F: FnMut(&str) -> io::Result<()>> {
+  buffer: Vec<u8>,
+  emit: F,
+}
+
+impl<F: FnMut(&str) -> io::Result<()>> ChunkWriter<F> {
+  const CHUNK_SIZE: usize = 16 * 1024;
+
+  fn new(emit: F) -> Self {
+    Self {
+      buffer: Vec::with_capacity(Self::CHUNK_SIZE * 2),
+      emit,
+    }
+  }
+
+  /// Emits whatever is left in the buffer
+  fn finish(&mut self) -> io::Result<()> {
+    if self.buffer.is_empty() {
+      return Ok(());
+    }
+    // The HTML writer only writes whole strs, so the buffer always ends on a char boundary
+    let chunk = std::str::from_utf8(&self.buffer)
+      .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    (self.emit)(chunk)?;
+    self.buffer.clear();
+    Ok(())
+  }
+}
+
+impl<F: FnMut(&str) -> io::Result<()>> io::Write for ChunkWriter<F> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.buffer.extend_from_slice(buf);
+    if self.buffer.len() >= Self::CHUNK_SIZE {
+      self.finish()?;
+    }
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+/// The outcome of rendering one document of a batch. Exactly one of output and error is set
+#[napi(object)]
+pub struct BatchResult {
+  pub output: Option<HTMLOutput>,
+  pub error: Option<String>,
+  /// The code of the error, e.g. "PANICKED"
+  pub code: Option<String>,
+}
+
+/// Processes many markdown documents in parallel on a pool of worker threads
+/// A document that fails to render reports its error in its own result, the rest of
+/// the batch is unaffected
+/// Input: string[], optional options object shared by every document
+/// Output: {output?: {toc: string, content: string}, error?: string}[], in input order
+#[napi]
+pub fn process_markdown_batch(
+  env: Env,
+  inputs: Vec<String>,
+  options: Option<MarkdownOptions>,
+) -> Result<Vec<BatchResult>, napi::bindgen_prelude::Error> {
+  batch(&inputs, options).map_err(|e| js_error(&env, e))
+}
+
+fn batch(inputs: &[String], options: Option<MarkdownOptions>) -> Result<Vec<BatchResult>, Error> {
+  let renderer = Renderer::from_options(options.unwrap_or_default())?;
+  let results = renderer
+    .render_batch(inputs)
+    .into_iter()
+    .map(|result| match result {
+      Ok(output) => BatchResult {
+        output: Some(output),
+        error: None,
+        code: None,
+      },
+      Err(e) => BatchResult {
+        output: None,
+        error: Some(e.to_string()),
+        code: Some(e.code().into()),
+      },
+    })
+    .collect();
+  Ok(results)
+}
+
+/// Renders markdown with a configuration that is validated once, up front
+/// Construct it with the options, themes, language aliases and hooks to use, then call
+/// render for every document
+#[napi(custom_finalize)]
+pub struct MarkdownRenderer {
+  renderer: Renderer,
+  hooks: JsHookRefs,
+}
+
+#[napi]
+impl MarkdownRenderer {
+  #[napi(constructor)]
+  pub fn new(
+    env: Env,
+    config: Option<RendererConfig>,
+    hooks: Option<RendererHooks>,
+  ) -> Result<Self, napi::bindgen_prelude::Error> {
+    let renderer = Renderer::new(config.unwrap_or_default()).map_err(|e| js_error(&env, e))?;
+    let hooks = hooks.unwrap_or_default();
+    let hooks = JsHookRefs {
+      slugify: hooks.slugify.map(|f| env.create_reference(f)).transpose()?,
+      highlight: hooks
+        .highlight
+        .map(|f| env.create_reference(f))
+        .transpose()?,
+      resolve_link: hooks
+        .resolve_link
+        .map(|f| env.create_reference(f))
+        .transpose()?,
+      image_info: hooks
+        .image_info
+        .map(|f| env.create_reference(f))
+        .transpose()?,
+    };
+    Ok(Self { renderer, hooks })
+  }
+
+  /// Processes markdown to html with this renderer's configuration
+  /// Output: {toc: string, content: string}
+  #[napi]
+  pub fn render(
+    &self,
+    env: Env,
+    input: String,
+  ) -> Result<HTMLOutput, napi::bindgen_prelude::Error> {
+    let hooks = JsHooks {
+      env: &env,
+      refs: &self.hooks,
+      error: RefCell::new(None),
+    };
+    let output = self.renderer.render(&input, &hooks);
+    if let Some(e) = hooks.error.take() {
+      return Err(e);
+    }
+    output.map_err(|e| js_error(&env, e))
+  }
+
+  /// CSS for the configured theme and dark theme, if any were set
+  #[napi]
+  pub fn stylesheet(&self) -> Option<String> {
+    self.renderer.stylesheet()
+  }
+}
+
+impl ObjectFinalize for MarkdownRenderer {
+  fn finalize(mut self, env: Env) -> Result<(), napi::bindgen_prelude::Error> {
+    for hook in [
+      &mut self.hooks.slugify,
+      &mut self.hooks.highlight,
+      &mut self.hooks.resolve_link,
+      &mut self.hooks.image_info,
+    ] {
+      if let Some(mut hook) = hook.take() {
+        hook.unref(env)?;
+      }
+    }
+    Ok(())
+  }
+}
+
+/// JavaScript callbacks kept alive for as long as their renderer
+struct JsHookRefs {
+  slugify: Option<Ref<()>>,
+  highlight: Option<Ref<()>>,
+  resolve_link: Option<Ref<()>>,
+  image_info: Option<Ref<()>>,
+}
+
+/// Calls the JavaScript hooks of a renderer. They can only be called on the JS thread, so
+/// this lives for a single render
+struct JsHooks<'a> {
+  env: &'a Env,
+  refs: &'a JsHookRefs,
+  // The exception thrown by a hook, rethrown as is once rendering ends
+  error: RefCell<Option<napi::Error>>,
+}
+
+impl JsHooks<'_> {
+  /// Calls `hook` with string arguments and turns what it returned into a result with
+  /// `convert`. Undefined and null always mean the hook had no opinion
+  fn call<T>(
+    &self,
+    hook: &Option<Ref<()>>,
+    args: &[&str],
+    convert: impl FnOnce(JsUnknown, ValueType) -> Result<T, napi::Error>,
+  ) -> Result<Option<T>, Error> {
+    let hook = match hook {
+      Some(hook) => hook,
+      None => return Ok(None),
+    };
+    let call = || -> Result<Option<T>, napi::Error> {
+      let hook: JsFunction = self.env.get_reference_value(hook)?;
+      let args = args
+        .iter()
+        .map(|arg| self.env.create_string(arg))
+        .collect::<Result<Vec<_>, _>>()?;
+      let value = hook.call(None, &args)?;
+      match value.get_type()? {
+        ValueType::Undefined | ValueType::Null => Ok(None),
+        kind => convert(value, kind).map(Some),
+      }
+    };
+    call().map_err(|e| {
+      let reason = e.reason.clone();
+      self.error.borrow_mut().get_or_insert(e);
+      Error::Hook(reason)
+    })
+  }
+}
+
+impl Hooks for JsHooks<'_> {
+  fn slugify(&self, text: &str) -> Result<Option<String>, Error> {
+    self.call(&self.refs.slugify, &[text], to_string)
+  }
+
+  fn highlight(&self, lang: &str, source: &str) -> Result<Option<String>, Error> {
+    self.call(&self.refs.highlight, &[lang, source], to_string)
+  }
+
+  fn resolve_link(&self, url: &str) -> Result<Option<ResolvedLink>, Error> {
+    self.call(&self.refs.resolve_link, &[url], |value, kind| match kind {
+      ValueType::String => to_string(value, kind).map(ResolvedLink::Url),
+      ValueType::Boolean if !value.coerce_to_bool()?.get_value()? => Ok(ResolvedLink::Unresolved),
+      other => Err(expected(&format!(
+        "a string, false or undefined, got {other}"
+      ))),
+    })
+  }
+
+  fn image_info(&self, src: &str) -> Result<Option<ImageInfo>, Error> {
+    self.call(&self.refs.image_info, &[src], |value, kind| match kind {
+      // Safe as the value came from this env, on the JS thread
+      ValueType::Object => unsafe { ImageInfo::from_napi_value(self.env.raw(), value.raw()) },
+      other => Err(expected(&format!("an object or undefined, got {other}"))),
+    })
+  }
+}
+
+/// Reads the string a hook returned
+fn to_string(value: JsUnknown, kind: ValueType) -> Result<String, napi::Error> {
+  match kind {
+    ValueType::String => value.coerce_to_string()?.into_utf8()?.into_owned(),
+    other => Err(expected(&format!("a string or undefined, got {other}"))),
+  }
+}
+
+fn expected(what: &str) -> napi::Error {
+  napi::Error::new(
+    napi::Status::StringExpected,
+    format!("hooks must return {what}"),
+  )
+}
+
+/// Builds the exception thrown for `error`, an Error whose `code` is `error.code()`
+fn js_error(env: &Env, error: Error) -> napi::Error {
+  let error = napi::Error::new(error.code(), error.to_string());
+  JsError::from(error).into_unknown(*env).into()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_render_task_matches_sync_render() {
+    let input = "# Title\n\n```rust\nlet x = 1;\n```\n".to_string();
+    let mut task = RenderTask {
+      input: input.clone(),
+      options: None,
+      error: None,
+    };
+    let output = task.compute().unwrap();
+    let expected = render(&input, None).unwrap();
+    assert_eq!(output.content, expected.content);
+    assert_eq!(output.toc, expected.toc);
+  }
+
+  #[test]
+  fn test_chunk_writer_emits_whole_chunks() {
+    use std::io::Write;
+
+    let mut chunks = Vec::new();
+    let mut writer = ChunkWriter::new(|chunk: &str| {
+      chunks.push(chunk.to_string());
+      Ok(())
+    });
+    let piece = "<p>héllo</p>".repeat(1000);
+    for _ in 0..5 {
+      writer.write_all(piece.as_bytes()).unwrap();
+    }
+    writer.finish().unwrap();
+    drop(writer);
+
+    assert!(chunks.len() > 1);
+    assert_eq!(chunks.concat(), piece.repeat(5));
+  }
+
+  #[test]
+  fn test_process_markdown_batch_keeps_order() {
+    let inputs = (0..32)
+      .map(|i| format!("# Doc {i}\n\n```rust\nfn f{i}() {{}}\n```\n"))
+      .collect::<Vec<_>>();
+    let results = batch(&inputs, None).unwrap();
+    assert_eq!(results.len(), inputs.len());
+    for (i, result) in results.iter().enumerate() {
+      assert!(result.error.is_none());
+      let output = result.output.as_ref().unwrap();
+      assert!(output.content.contains(&format!(r#"id="doc-{i}""#)));
+    }
+  }
+}
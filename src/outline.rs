@@ -0,0 +1,151 @@
+use pulldown_cmark::{Event, LinkType, Tag};
+use serde::{Deserialize, Serialize};
+
+/// Everything the renderer saw in a document, for tooling like link checkers and sitemaps
+#[cfg_attr(feature = "node", napi(object))]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Outline {
+  /// The headings that made it into the table of contents
+  pub headings: Vec<OutlineHeading>,
+  /// Every link, after the link rules ran
+  pub links: Vec<OutlineLink>,
+  pub images: Vec<OutlineImage>,
+  /// Footnotes by label, in the order they were first referenced or defined
+  pub footnotes: Vec<OutlineFootnote>,
+  pub code_blocks: Vec<OutlineCodeBlock>,
+}
+
+#[cfg_attr(feature = "node", napi(object))]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutlineHeading {
+  pub level: u8,
+  pub slug: String,
+  /// The heading's plain text
+  pub text: String,
+}
+
+#[cfg_attr(feature = "node", napi(object))]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutlineLink {
+  pub url: String,
+  /// The link's plain text
+  pub text: String,
+}
+
+#[cfg_attr(feature = "node", napi(object))]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutlineImage {
+  pub src: String,
+  pub alt: String,
+  pub title: Option<String>,
+}
+
+#[cfg_attr(feature = "node", napi(object))]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutlineFootnote {
+  pub label: String,
+  /// How many times the footnote is referenced
+  pub references: u32,
+  /// Whether the document defines it, so a link checker can flag dangling references
+  pub defined: bool,
+}
+
+#[cfg_attr(feature = "node", napi(object))]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutlineCodeBlock {
+  /// The language the block was rendered as, after detection and defaults. Empty when it
+  /// has none
+  pub lang: String,
+  pub lines: u32,
+}
+
+/// Collects the outline while the renderer walks a document
+#[derive(Default)]
+pub(crate) struct OutlineBuilder {
+  outline: Outline,
+  // Links and images whose text is still being collected, innermost last
+  open: Vec<Open>,
+}
+
+enum Open {
+  Link(usize),
+  Image(usize),
+}
+
+impl OutlineBuilder {
+  /// Records the links, images and footnotes among the document's events
+  pub(crate) fn event(&mut self, ev: &Event) {
+    match ev {
+      Event::Start(Tag::Link(kind, url, _)) => {
+        self.open.push(Open::Link(self.outline.links.len()));
+        self.outline.links.push(OutlineLink {
+          // Email autolinks only carry the address
+          url: match kind {
+            LinkType::Email => format!("mailto:{url}"),
+            _ => url.to_string(),
+          },
+          text: String::new(),
+        });
+      }
+      Event::Start(Tag::Image(_, src, title)) => {
+        self.open.push(Open::Image(self.outline.images.len()));
+        self.outline.images.push(OutlineImage {
+          src: src.to_string(),
+          alt: String::new(),
+          title: (!title.is_empty()).then(|| title.to_string()),
+        });
+      }
+      Event::End(Tag::Link(..)) | Event::End(Tag::Image(..)) => {
+        self.open.pop();
+      }
+      Event::Text(text) | Event::Code(text) => {
+        for open in &self.open {
+          match *open {
+            Open::Link(i) => self.outline.links[i].text.push_str(text),
+            Open::Image(i) => self.outline.images[i].alt.push_str(text),
+          }
+        }
+      }
+      Event::FootnoteReference(label) => self.footnote(label).references += 1,
+      Event::Start(Tag::FootnoteDefinition(label)) => self.footnote(label).defined = true,
+      _ => {}
+    }
+  }
+
+  pub(crate) fn heading(&mut self, level: u8, slug: &str, text: &str) {
+    self.outline.headings.push(OutlineHeading {
+      level,
+      slug: slug.into(),
+      text: text.into(),
+    });
+  }
+
+  pub(crate) fn code_block(&mut self, lang: &str, source: &str) {
+    self.outline.code_blocks.push(OutlineCodeBlock {
+      lang: lang.into(),
+      lines: source.lines().count() as u32,
+    });
+  }
+
+  pub(crate) fn finish(self) -> Outline {
+    self.outline
+  }
+
+  fn footnote(&mut self, label: &str) -> &mut OutlineFootnote {
+    let footnotes = &mut self.outline.footnotes;
+    let i = match footnotes.iter().position(|f| f.label == label) {
+      Some(i) => i,
+      None => {
+        footnotes.push(OutlineFootnote {
+          label: label.into(),
+          references: 0,
+          defined: false,
+        });
+        footnotes.len() - 1
+      }
+    };
+    &mut footnotes[i]
+  }
+}
@@ -0,0 +1,330 @@
+//! Python bindings, exposed through PyO3
+use crate::{
+  render_markdown, Error, ExternalLinkOptions, HTMLOutput, HighlightCacheOptions, ImageInfo,
+  ImageOptions, LinkOptions, MarkdownExtensions, MarkdownOptions, Outline, SearchIndexOptions,
+};
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::collections::HashMap;
+
+/// A rendered document
+#[pyclass(name = "HtmlOutput", frozen, get_all)]
+pub struct PyHtmlOutput {
+  /// The table of contents as an HTML list, or None when the document has no headings
+  pub toc: Option<String>,
+  pub content: String,
+  /// (code, message) pairs for problems that didn't stop the document from rendering
+  pub warnings: Vec<(String, String)>,
+  /// Link and image targets the link rules couldn't resolve
+  pub unresolved_links: Vec<String>,
+  pub outline: Py<PyOutline>,
+  pub word_count: u32,
+  pub reading_time_minutes: u32,
+  /// (text, html) of the start of the document
+  pub excerpt: (String, String),
+  /// (slug, title, level, text) for every heading, when `search_index` was passed
+  pub sections: Option<Vec<(String, String, u8, String)>>,
+}
+
+/// The headings, links, images, footnotes and code blocks of a document, as tuples
+#[pyclass(name = "Outline", frozen, get_all)]
+pub struct PyOutline {
+  /// (level, slug, text)
+  pub headings: Vec<(u8, String, String)>,
+  /// (url, text)
+  pub links: Vec<(String, String)>,
+  /// (src, alt, title)
+  pub images: Vec<(String, String, Option<String>)>,
+  /// (label, references, defined)
+  pub footnotes: Vec<(String, u32, bool)>,
+  /// (lang, lines)
+  pub code_blocks: Vec<(String, u32)>,
+}
+
+impl From<Outline> for PyOutline {
+  fn from(outline: Outline) -> Self {
+    Self {
+      headings: outline
+        .headings
+        .into_iter()
+        .map(|h| (h.level, h.slug, h.text))
+        .collect(),
+      links: outline.links.into_iter().map(|l| (l.url, l.text)).collect(),
+      images: outline
+        .images
+        .into_iter()
+        .map(|i| (i.src, i.alt, i.title))
+        .collect(),
+      footnotes: outline
+        .footnotes
+        .into_iter()
+        .map(|f| (f.label, f.references, f.defined))
+        .collect(),
+      code_blocks: outline
+        .code_blocks
+        .into_iter()
+        .map(|c| (c.lang, c.lines))
+        .collect(),
+    }
+  }
+}
+
+#[pymethods]
+impl PyHtmlOutput {
+  fn __repr__(&self) -> String {
+    format!(
+      "HtmlOutput(toc={}, content={:?})",
+      self
+        .toc
+        .as_deref()
+        .map_or("None".into(), |toc| format!("{toc:?}")),
+      self.content
+    )
+  }
+}
+
+impl PyHtmlOutput {
+  fn new(py: Python<'_>, output: HTMLOutput) -> PyResult<Self> {
+    Ok(Self {
+      toc: output.toc,
+      content: output.content,
+      warnings: output
+        .warnings
+        .into_iter()
+        .map(|warning| (warning.code, warning.message))
+        .collect(),
+      unresolved_links: output.unresolved_links,
+      outline: Py::new(py, PyOutline::from(output.outline))?,
+      word_count: output.word_count,
+      reading_time_minutes: output.reading_time_minutes,
+      excerpt: (output.excerpt.text, output.excerpt.html),
+      sections: output.sections.map(|sections| {
+        sections
+          .into_iter()
+          .map(|s| (s.slug, s.title, s.level, s.text))
+          .collect()
+      }),
+    })
+  }
+}
+
+/// Renders markdown to HTML, producing the same markup as the Node addon's
+/// `processMarkdownToHtml`.
+/// `extensions` maps extension names such as "tables" or "smart_punctuation" to booleans,
+/// `highlight_cache` takes a "capacity" and a "directory", `raw_html` is one of "allow",
+/// "escape" or "strip", `links` takes a "base_url", "path", "strip_md_extension" and
+/// "rewrites", `external_links` a "site_origin" and a "class", `search_index` an
+/// "include_code", and `images` "lazy", "async_decoding", "figures" and a "manifest" that maps
+/// image sources to dicts of "width", "height", "srcset" and "sizes"
+#[pyfunction]
+#[pyo3(signature = (
+  input,
+  *,
+  extensions = None,
+  detect_language = None,
+  default_language = None,
+  highlight_cache = None,
+  max_highlight_bytes = None,
+  highlight_timeout_ms = None,
+  raw_html = None,
+  excerpt_words = None,
+  words_per_minute = None,
+  search_index = None,
+  images = None,
+  links = None,
+  external_links = None,
+  callouts = None,
+  math = None,
+  diagrams = None,
+))]
+// Every option is a keyword argument on the Python side
+#[allow(clippy::too_many_arguments)]
+fn process_markdown_to_html(
+  py: Python<'_>,
+  input: &str,
+  extensions: Option<&PyDict>,
+  detect_language: Option<bool>,
+  default_language: Option<String>,
+  highlight_cache: Option<&PyDict>,
+  max_highlight_bytes: Option<u32>,
+  highlight_timeout_ms: Option<u32>,
+  raw_html: Option<String>,
+  excerpt_words: Option<u32>,
+  words_per_minute: Option<u32>,
+  search_index: Option<&PyDict>,
+  images: Option<&PyDict>,
+  links: Option<&PyDict>,
+  external_links: Option<&PyDict>,
+  callouts: Option<bool>,
+  math: Option<bool>,
+  diagrams: Option<Vec<String>>,
+) -> PyResult<PyHtmlOutput> {
+  let options = MarkdownOptions {
+    extensions: extensions.map(markdown_extensions).transpose()?,
+    detect_language,
+    default_language,
+    highlight_cache: highlight_cache.map(highlight_cache_options).transpose()?,
+    max_highlight_bytes,
+    highlight_timeout_ms,
+    raw_html,
+    excerpt_words,
+    words_per_minute,
+    search_index: search_index.map(search_index_options).transpose()?,
+    images: images.map(image_options).transpose()?,
+    links: links.map(link_options).transpose()?,
+    external_links: external_links.map(external_link_options).transpose()?,
+    callouts,
+    math,
+    diagrams,
+  };
+  // Rendering never touches Python objects, so let other threads run meanwhile
+  let output = py.allow_threads(|| render_markdown(input, options))?;
+  PyHtmlOutput::new(py, output)
+}
+
+fn markdown_extensions(dict: &PyDict) -> PyResult<MarkdownExtensions> {
+  let mut extensions = MarkdownExtensions::default();
+  for (key, value) in dict {
+    let key: &str = key.extract()?;
+    let value = Some(value.extract::<bool>()?);
+    match key {
+      "tables" => extensions.tables = value,
+      "footnotes" => extensions.footnotes = value,
+      "strikethrough" => extensions.strikethrough = value,
+      "tasklists" => extensions.tasklists = value,
+      "smart_punctuation" => extensions.smart_punctuation = value,
+      "heading_attributes" => extensions.heading_attributes = value,
+      _ => return Err(PyValueError::new_err(format!("unknown extension {key:?}"))),
+    }
+  }
+  Ok(extensions)
+}
+
+fn highlight_cache_options(dict: &PyDict) -> PyResult<HighlightCacheOptions> {
+  let mut options = HighlightCacheOptions::default();
+  for (key, value) in dict {
+    let key: &str = key.extract()?;
+    match key {
+      "capacity" => options.capacity = value.extract()?,
+      "directory" => options.directory = value.extract()?,
+      _ => {
+        return Err(PyValueError::new_err(format!(
+          "unknown highlight cache option {key:?}"
+        )))
+      }
+    }
+  }
+  Ok(options)
+}
+
+fn search_index_options(dict: &PyDict) -> PyResult<SearchIndexOptions> {
+  let mut options = SearchIndexOptions::default();
+  for (key, value) in dict {
+    let key: &str = key.extract()?;
+    match key {
+      "include_code" => options.include_code = value.extract()?,
+      _ => {
+        return Err(PyValueError::new_err(format!(
+          "unknown search index option {key:?}"
+        )))
+      }
+    }
+  }
+  Ok(options)
+}
+
+fn image_options(dict: &PyDict) -> PyResult<ImageOptions> {
+  let mut options = ImageOptions::default();
+  for (key, value) in dict {
+    let key: &str = key.extract()?;
+    match key {
+      "lazy" => options.lazy = value.extract()?,
+      "async_decoding" => options.async_decoding = value.extract()?,
+      "figures" => options.figures = value.extract()?,
+      "manifest" => {
+        let manifest: HashMap<String, &PyDict> = value.extract()?;
+        options.manifest = Some(
+          manifest
+            .into_iter()
+            .map(|(src, info)| Ok((src, image_info(info)?)))
+            .collect::<PyResult<_>>()?,
+        );
+      }
+      _ => {
+        return Err(PyValueError::new_err(format!(
+          "unknown image option {key:?}"
+        )))
+      }
+    }
+  }
+  Ok(options)
+}
+
+fn image_info(dict: &PyDict) -> PyResult<ImageInfo> {
+  let mut info = ImageInfo::default();
+  for (key, value) in dict {
+    let key: &str = key.extract()?;
+    match key {
+      "width" => info.width = value.extract()?,
+      "height" => info.height = value.extract()?,
+      "srcset" => info.srcset = value.extract()?,
+      "sizes" => info.sizes = value.extract()?,
+      _ => return Err(PyValueError::new_err(format!("unknown image info {key:?}"))),
+    }
+  }
+  Ok(info)
+}
+
+fn link_options(dict: &PyDict) -> PyResult<LinkOptions> {
+  let mut options = LinkOptions::default();
+  for (key, value) in dict {
+    let key: &str = key.extract()?;
+    match key {
+      "base_url" => options.base_url = value.extract()?,
+      "path" => options.path = value.extract()?,
+      "strip_md_extension" => options.strip_md_extension = value.extract()?,
+      "rewrites" => options.rewrites = value.extract()?,
+      _ => {
+        return Err(PyValueError::new_err(format!(
+          "unknown link option {key:?}"
+        )))
+      }
+    }
+  }
+  Ok(options)
+}
+
+fn external_link_options(dict: &PyDict) -> PyResult<ExternalLinkOptions> {
+  let mut options = ExternalLinkOptions::default();
+  for (key, value) in dict {
+    let key: &str = key.extract()?;
+    match key {
+      "site_origin" => options.site_origin = value.extract()?,
+      "class" => options.class = value.extract()?,
+      _ => {
+        return Err(PyValueError::new_err(format!(
+          "unknown external link option {key:?}"
+        )))
+      }
+    }
+  }
+  Ok(options)
+}
+
+impl From<Error> for PyErr {
+  fn from(e: Error) -> Self {
+    match e {
+      Error::InvalidConfig(_) => PyValueError::new_err(e.to_string()),
+      _ => PyRuntimeError::new_err(e.to_string()),
+    }
+  }
+}
+
+#[pymodule]
+fn femark(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
+  m.add_function(wrap_pyfunction!(process_markdown_to_html, m)?)?;
+  m.add_class::<PyHtmlOutput>()?;
+  m.add_class::<PyOutline>()?;
+  Ok(())
+}
@@ -0,0 +1,1445 @@
+use crate::cache::{HighlightCache, DEFAULT_CAPACITY};
+use crate::callouts::{self, Callouts, Markers};
+use crate::detect::detect_language;
+use crate::images::{ImageRules, Images};
+use crate::links::{ExternalLinks, LinkRules, ResolvedLink};
+use crate::math::{self, Formulas, Math};
+use crate::outline::OutlineBuilder;
+use crate::search::SectionsBuilder;
+use crate::summary::{
+  is_more_marker, SummaryBuilder, DEFAULT_EXCERPT_WORDS, DEFAULT_WORDS_PER_MINUTE,
+};
+use crate::themes::{find_theme, stylesheet, Theme};
+use crate::{
+  generate_toc, highlight_code, write_code_escaped, Error, HTMLOutput, HighlightError, ImageInfo,
+  Lang, Limits, MarkdownOptions, RendererConfig, SearchIndexOptions, Toc, TocEntry, Warning, LANGS,
+};
+use pulldown_cmark::escape::escape_html;
+use pulldown_cmark::{
+  html, CodeBlockKind, CowStr, Event, HeadingLevel, LinkType, Options, Parser, Tag,
+};
+use rayon::prelude::*;
+use slug::slugify;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Callbacks the renderer consults while walking a document. Every hook is optional,
+/// returning `Ok(None)` keeps femark's own behavior
+pub trait Hooks {
+  /// Builds the anchor slug for a heading from its plain text
+  fn slugify(&self, _text: &str) -> Result<Option<String>, Error> {
+    Ok(None)
+  }
+  /// Highlights a code block in a language femark has no grammar for. The returned
+  /// markup is placed inside the block's `<pre>` as is
+  fn highlight(&self, _lang: &str, _source: &str) -> Result<Option<String>, Error> {
+    Ok(None)
+  }
+  /// Resolves the target of a link or image, e.g. to map links between markdown files onto
+  /// site URLs. The link rules only apply to targets this returns `Ok(None)` for
+  fn resolve_link(&self, _url: &str) -> Result<Option<ResolvedLink>, Error> {
+    Ok(None)
+  }
+  /// Supplies the dimensions and sources of an image, by its src after the link rules ran.
+  /// Only consulted when the `images` option is set
+  fn image_info(&self, _src: &str) -> Result<Option<ImageInfo>, Error> {
+    Ok(None)
+  }
+}
+
+/// Hooks that leave every decision to femark
+pub struct NoHooks;
+
+impl Hooks for NoHooks {}
+
+/// A validated rendering configuration, built once and reused for many documents
+pub struct Renderer {
+  parser_options: Options,
+  detect_language: bool,
+  default_language: String,
+  aliases: HashMap<String, String>,
+  theme: Option<&'static Theme>,
+  dark_theme: Option<&'static Theme>,
+  cache: Option<Arc<HighlightCache>>,
+  limits: Limits,
+  raw_html: RawHtml,
+  links: LinkRules,
+  external_links: Option<ExternalLinks>,
+  excerpt_words: usize,
+  words_per_minute: u32,
+  search_index: Option<SearchIndexOptions>,
+  images: Option<ImageRules>,
+  callouts: bool,
+  math: bool,
+  diagrams: Vec<String>,
+}
+
+/// What happens to HTML written in the markdown itself
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum RawHtml {
+  Allow,
+  Escape,
+  Strip,
+}
+
+impl RawHtml {
+  fn parse(name: Option<&str>) -> Result<Self, Error> {
+    match name {
+      None | Some("allow") => Ok(Self::Allow),
+      Some("escape") => Ok(Self::Escape),
+      Some("strip") => Ok(Self::Strip),
+      Some(other) => Err(Error::InvalidConfig(format!(
+        "rawHtml must be \"allow\", \"escape\" or \"strip\", got {other:?}"
+      ))),
+    }
+  }
+
+  /// Safe mode, for markdown that can't be trusted. Link and image URLs are filtered too
+  fn is_safe(self) -> bool {
+    self != Self::Allow
+  }
+}
+
+impl Renderer {
+  pub fn new(config: RendererConfig) -> Result<Self, Error> {
+    let theme = config.theme.as_deref().map(theme_by_name).transpose()?;
+    let dark_theme = config
+      .dark_theme
+      .as_deref()
+      .map(theme_by_name)
+      .transpose()?;
+
+    let aliases = config.languages.unwrap_or_default();
+    for (alias, lang) in &aliases {
+      if LANGS.get(lang).is_none() {
+        return Err(Error::InvalidConfig(format!(
+          "language alias {alias:?} points to unknown language {lang:?}"
+        )));
+      }
+    }
+
+    let options = config.options.unwrap_or_default();
+    let raw_html = RawHtml::parse(options.raw_html.as_deref())?;
+    let words_per_minute = match options.words_per_minute {
+      Some(0) => {
+        return Err(Error::InvalidConfig(
+          "wordsPerMinute must be at least 1".into(),
+        ))
+      }
+      wpm => wpm.unwrap_or(DEFAULT_WORDS_PER_MINUTE),
+    };
+    let external_links = options
+      .external_links
+      .as_ref()
+      .map(ExternalLinks::new)
+      .transpose()?;
+    let cache = options
+      .highlight_cache
+      .as_ref()
+      .map(|cache| {
+        HighlightCache::shared(
+          cache.capacity.map_or(DEFAULT_CAPACITY, |c| c as usize),
+          cache.directory.as_ref().map(Into::into),
+        )
+      })
+      .transpose()?;
+
+    Ok(Self {
+      parser_options: parser_options(&options),
+      detect_language: options.detect_language.unwrap_or(false),
+      default_language: options.default_language.unwrap_or_default(),
+      limits: Limits {
+        max_bytes: options.max_highlight_bytes.map(|bytes| bytes as usize),
+        timeout: options
+          .highlight_timeout_ms
+          .map(|ms| Duration::from_millis(ms.into())),
+      },
+      raw_html,
+      links: options
+        .links
+        .as_ref()
+        .map(LinkRules::new)
+        .unwrap_or_default(),
+      external_links,
+      excerpt_words: options.excerpt_words.unwrap_or(DEFAULT_EXCERPT_WORDS) as usize,
+      words_per_minute,
+      search_index: options.search_index,
+      images: options.images.as_ref().map(ImageRules::new),
+      callouts: options.callouts.unwrap_or(false),
+      math: options.math.unwrap_or(false),
+      diagrams: options.diagrams.unwrap_or_default(),
+      aliases,
+      theme,
+      dark_theme,
+      cache,
+    })
+  }
+
+  pub fn from_options(options: MarkdownOptions) -> Result<Self, Error> {
+    Self::new(RendererConfig {
+      options: Some(options),
+      ..Default::default()
+    })
+  }
+
+  /// CSS for the configured themes, if any
+  pub fn stylesheet(&self) -> Option<String> {
+    stylesheet(self.theme, self.dark_theme)
+  }
+
+  /// Renders markdown to HTML, syntax highlighting the code blocks and collecting a table
+  /// of contents from the headings
+  pub fn render(&self, input: &str, hooks: &dyn Hooks) -> Result<HTMLOutput, Error> {
+    let mut content = String::with_capacity(input.len() + input.len() / 2);
+    let (input, formulas) = self.preprocess(input);
+    let mut output = self.render_events(&input, &formulas, hooks, |events| {
+      html::push_html(&mut content, events);
+      Ok(())
+    })?;
+    output.content = content;
+    Ok(output)
+  }
+
+  /// Renders markdown like `render`, but writes the HTML to `writer` as it is produced
+  /// instead of buffering it. The returned output has an empty `content`
+  pub fn render_to_writer<W: io::Write>(
+    &self,
+    input: &str,
+    hooks: &dyn Hooks,
+    writer: W,
+  ) -> Result<HTMLOutput, Error> {
+    let (input, formulas) = self.preprocess(input);
+    self.render_events(&input, &formulas, hooks, |events| {
+      html::write_html(writer, events)
+    })
+  }
+
+  /// Renders many documents in parallel on rayon's thread pool. Results are in input
+  /// order, and a document that fails to render doesn't affect the others
+  pub fn render_batch<S: AsRef<str> + Sync>(&self, inputs: &[S]) -> Vec<Result<HTMLOutput, Error>> {
+    inputs
+      .par_iter()
+      .map(|input| {
+        panic::catch_unwind(AssertUnwindSafe(|| self.render(input.as_ref(), &NoHooks)))
+          .unwrap_or_else(|panic| {
+            let message = panic
+              .downcast_ref::<&str>()
+              .map(ToString::to_string)
+              .or_else(|| panic.downcast_ref::<String>().cloned())
+              .unwrap_or_default();
+            Err(Error::Panicked(message))
+          })
+      })
+      .collect()
+  }
+
+  /// Renders markdown like `render_to_writer`, for sinks that implement `fmt::Write`
+  pub fn render_to_fmt<W: fmt::Write>(
+    &self,
+    input: &str,
+    hooks: &dyn Hooks,
+    writer: &mut W,
+  ) -> Result<HTMLOutput, Error> {
+    self.render_to_writer(input, hooks, FmtWriter(writer))
+  }
+
+  /// Rewrites the syntaxes pulldown-cmark doesn't know into ones it does
+  fn preprocess<'a>(&self, input: &'a str) -> (Cow<'a, str>, Formulas) {
+    let input = match self.callouts {
+      true => callouts::containers(input),
+      false => Cow::Borrowed(input),
+    };
+    if !self.math {
+      return (input, Formulas::default());
+    }
+    match math::extract(&input) {
+      (Cow::Owned(extracted), formulas) => (Cow::Owned(extracted), formulas),
+      (Cow::Borrowed(_), formulas) => (input, formulas),
+    }
+  }
+
+  /// Walks the markdown events, rewriting code blocks and headings, and hands the
+  /// resulting stream to `emit` to be turned into HTML
+  fn render_events<'a>(
+    &self,
+    input: &'a str,
+    formulas: &Formulas,
+    hooks: &dyn Hooks,
+    emit: impl FnOnce(&mut dyn Iterator<Item = Event<'a>>) -> io::Result<()>,
+  ) -> Result<HTMLOutput, Error> {
+    let parser = Parser::new_ext(input, self.parser_options);
+    // Link targets are resolved and recorded as written
+    let stream = formulas.literal(parser);
+    let langs = &LANGS;
+    let mut toc: Toc = Vec::new();
+    let mut hook_error: Option<Error> = None;
+    let image_error = RefCell::new(None);
+    let mut warnings: Vec<Warning> = Vec::new();
+    let mut unresolved_links: Vec<String> = Vec::new();
+    let mut outline = OutlineBuilder::default();
+    let mut markers = Markers::new(self.callouts);
+    let summary = RefCell::new(SummaryBuilder::new(self.excerpt_words, self.callouts));
+    let mut sections = self
+      .search_index
+      .as_ref()
+      .map(|options| SectionsBuilder::new(options.include_code.unwrap_or(false)));
+
+    struct Code {
+      lang: String,
+      source: String,
+    }
+    let mut current_code: Option<Code> = None;
+    // Code blocks are built here, so the buffer only grows for the biggest one
+    let mut scratch = String::new();
+
+    struct Heading<'e> {
+      level: HeadingLevel,
+      // From `{#id .class}` with the heading attributes extension
+      frag: Option<String>,
+      class: Vec<String>,
+      // The inline content, rendered once the heading ends
+      events: Vec<Event<'e>>,
+      plain_text: String,
+    }
+    let mut current_heading: Option<Heading<'a>> = None;
+
+    let mut blockquote_depth = 0;
+    let mut in_figcaption = false;
+
+    // Events swallowed into a code block or heading are dropped rather than replaced with
+    // empty placeholders
+    let stream = stream.filter_map(|ev| {
+      debug!(?ev, "Got markdown event");
+      let raw = matches!(ev, Event::Html(_));
+      let ev = match ev {
+        Event::Html(html) if is_more_marker(&html) => {
+          summary.borrow_mut().marker();
+          return None;
+        }
+        Event::Html(html) => match self.raw_html {
+          RawHtml::Allow => Event::Html(html),
+          // As text it goes through the same escaping, and heading capture, as any other
+          RawHtml::Escape => Event::Text(html),
+          RawHtml::Strip => return None,
+        },
+        // Email autolinks carry a bare address, which the HTML writer turns into a mailto:
+        Event::Start(Tag::Link(kind, dest, title)) if kind != LinkType::Email => {
+          let dest = self.resolve_link(dest, hooks, &mut unresolved_links, &mut hook_error);
+          Event::Start(Tag::Link(kind, self.filter_url(dest, false), title))
+        }
+        Event::Start(Tag::Image(kind, dest, title)) => {
+          let dest = self.resolve_link(dest, hooks, &mut unresolved_links, &mut hook_error);
+          Event::Start(Tag::Image(kind, self.filter_url(dest, true), title))
+        }
+        ev => ev,
+      };
+      // Everything that reads the text sees formulas as they were written
+      let plain = formulas.restore(&ev);
+      markers.plain(&plain, |ev| {
+        outline.event(ev);
+        summary.borrow_mut().event(ev);
+        // Headings start a section of their own, once the renderer gave them an anchor
+        if let (Some(sections), None) = (sections.as_mut(), &current_heading) {
+          sections.event(ev);
+        }
+      });
+      if let Some(heading) = current_heading.as_mut() {
+        if !matches!(ev, Event::End(Tag::Heading(..))) {
+          // Tags are left out of the slug and outline, whatever the raw HTML policy made of them
+          match plain.as_ref() {
+            Event::Text(text) | Event::Code(text) if !raw => heading.plain_text.push_str(text),
+            _ => {}
+          }
+          heading.events.push(ev);
+          return None;
+        }
+      }
+      match &ev {
+        Event::Start(Tag::CodeBlock(kind)) => {
+          let lang = match kind {
+            CodeBlockKind::Fenced(lang) => lang.to_string(),
+            CodeBlockKind::Indented => String::new(),
+          };
+          current_code = Some(Code {
+            lang,
+            source: Default::default(),
+          });
+          return None;
+        }
+        Event::Start(Tag::Heading(level, frag, class))
+          if blockquote_depth == 0 && !in_figcaption =>
+        {
+          current_heading = Some(Heading {
+            level: *level,
+            frag: frag.map(ToOwned::to_owned),
+            class: class.iter().map(ToString::to_string).collect(),
+            events: Vec::new(),
+            plain_text: "".into(),
+          });
+          return None;
+        }
+        Event::Start(Tag::BlockQuote) => {
+          blockquote_depth += 1;
+        }
+        Event::Html(html) => {
+          if html.contains("<figcaption>") {
+            in_figcaption = true;
+          }
+          if html.contains("</figcaption>") {
+            in_figcaption = false;
+          }
+        }
+        Event::End(Tag::CodeBlock(_)) => {
+          if let Some(mut current) = current_code.take() {
+            if self.math && current.lang == "math" {
+              outline.code_block(&current.lang, &current.source);
+              // Rendered like a `$$` formula on its own
+              let markup = math::markup(current.source.trim(), true);
+              return Some(Event::Html(format!("<p>{markup}</p>\n").into()));
+            }
+            if self.diagrams.contains(&current.lang) {
+              outline.code_block(&current.lang, &current.source);
+              return Some(Event::Html(diagram(&current.lang, &current.source).into()));
+            }
+            if current.lang.is_empty() {
+              // Detection parses the block with every grammar, so it stays within the
+              // highlighting limits too
+              let detect =
+                self.detect_language && self.limits.check_size("", &current.source).is_ok();
+              current.lang = detect
+                .then(|| detect_language(langs, &current.source, self.limits.timeout))
+                .flatten()
+                .unwrap_or(&self.default_language)
+                .to_string();
+            }
+            outline.code_block(&current.lang, &current.source);
+            let out = &mut scratch;
+            out.clear();
+            // Highlighting roughly triples the size of the source
+            out.reserve(current.source.len() * 3 + 128);
+
+            let key = self.aliases.get(&current.lang).unwrap_or(&current.lang);
+            let lang = langs.get(key);
+            out.push_str(r#"<div class="code-block">"#);
+
+            let tag = lang.map(|l| l.name).unwrap_or(&current.lang);
+            // The label comes straight from the info string, so it is escaped like any text
+            if !tag.is_empty() {
+              out.push_str(r#"<div class="language-tag">"#);
+              escape_html(&mut *out, tag).ok();
+              out.push_str("</div>");
+            }
+            out.push_str(r#"<pre class="code-block-inner" data-lang=""#);
+            escape_html(&mut *out, &current.lang).ok();
+            out.push_str(r#"">"#);
+
+            let start = out.len();
+            if let Err(e) = self.highlight_block(out, key, &current.source, &lang) {
+              // Drop whatever was highlighted before the failure
+              out.truncate(start);
+              // Blocks over the limits aren't worth another attempt through the hook
+              let markup = if e.exceeded_limits() {
+                Ok(None)
+              } else {
+                hooks.highlight(&current.lang, &current.source)
+              };
+              match markup {
+                Ok(Some(markup)) => out.push_str(&markup),
+                Ok(None) => {
+                  if !e.benign() {
+                    warn!("Highlight error: {}", e);
+                    warnings.push(e.into());
+                  }
+                  write_code_escaped(&mut *out, &current.source).ok();
+                }
+                Err(e) => {
+                  hook_error.get_or_insert(e);
+                }
+              }
+            }
+            out.push_str("</pre></div>");
+
+            // One allocation of the exact size, however much the buffer grew to
+            return Some(Event::Html(CowStr::Boxed(out.as_str().into())));
+          }
+        }
+        Event::End(Tag::Heading(_, _, _)) => {
+          if let Some(heading) = current_heading.take() {
+            let tag = match heading.level {
+              HeadingLevel::H1 => "h1",
+              HeadingLevel::H2 => "h2",
+              HeadingLevel::H3 => "h3",
+              HeadingLevel::H4 => "h4",
+              HeadingLevel::H5 => "h5",
+              HeadingLevel::H6 => "h6",
+            };
+            // An id given in the markdown wins over any slug
+            let anchor = match heading.frag {
+              Some(frag) => Ok(Some(frag)),
+              None => hooks.slugify(&heading.plain_text),
+            };
+            let anchor = match anchor {
+              Ok(Some(anchor)) => anchor,
+              Ok(None) => slugify(&heading.plain_text),
+              Err(e) => {
+                hook_error.get_or_insert(e);
+                slugify(&heading.plain_text)
+              }
+            };
+
+            outline.heading(heading.level as u8, &anchor, &heading.plain_text);
+            if let Some(sections) = sections.as_mut() {
+              sections.heading(heading.level as u8, &anchor, &heading.plain_text);
+            }
+            let anchor = escape_attribute(&anchor);
+            let class = match heading.class.is_empty() {
+              true => String::new(),
+              false => format!(r#" class="{}""#, escape_attribute(&heading.class.join(" "))),
+            };
+
+            // Links can't nest, so headings that contain one get an empty anchor beside
+            // their content instead of around it
+            let has_links = heading.events.iter().any(|ev| {
+              matches!(
+                ev,
+                Event::Start(Tag::Link(..)) | Event::FootnoteReference(_)
+              )
+            });
+            // The table of contents links every entry already, so it keeps only the text of
+            // links in the heading
+            let mut toc_text = String::new();
+            let toc_events = heading
+              .events
+              .iter()
+              .filter(|ev| {
+                !matches!(
+                  ev,
+                  Event::Start(Tag::Link(..))
+                    | Event::End(Tag::Link(..))
+                    | Event::FootnoteReference(_)
+                )
+              })
+              .cloned();
+            html::push_html(&mut toc_text, Math::new(toc_events, formulas));
+            let mut markup = String::new();
+            let events = heading.events.into_iter().map(|ev| self.decorate_link(ev));
+            let events = Math::new(events, formulas);
+            html::push_html(
+              &mut markup,
+              Images::new(events, self.images.as_ref(), hooks, &image_error),
+            );
+
+            let mut out = String::with_capacity(markup.len() + 2 * anchor.len() + 256);
+            if has_links {
+              write!(
+                &mut out,
+                r##"
+                        <{tag}{class}>
+                            <a id="{anchor}" class="anchor" href="#{anchor}" aria-hidden="true"></a>
+                            {markup}
+                        </{tag}>
+                        "##,
+              )
+              .ok();
+            } else {
+              write!(
+                &mut out,
+                r##"
+                        <{tag}{class}>
+                            <a id="{anchor}" class="anchor" href="#{anchor}">
+                                {markup}
+                            </a>
+                        </{tag}>
+                        "##,
+              )
+              .ok();
+            }
+
+            toc.push(TocEntry {
+              level: heading.level as u8,
+              text: toc_text,
+              slug: anchor,
+            });
+
+            return Some(Event::Html(out.into()));
+          }
+        }
+        Event::End(Tag::BlockQuote) => {
+          blockquote_depth -= 1;
+        }
+        Event::Text(_) => {
+          if let (Some(current), Event::Text(text)) = (current_code.as_mut(), plain.as_ref()) {
+            current.source.push_str(text);
+            return None;
+          }
+        }
+        _ => {}
+      }
+
+      Some(self.decorate_link(ev))
+    });
+    let stream = Math::new(stream, formulas);
+    let stream = Images::new(stream, self.images.as_ref(), hooks, &image_error);
+    // The excerpt is cut from what was rendered, so it looks like the content
+    let stream = stream.inspect(|ev| summary.borrow_mut().emitted(ev));
+    // After the excerpt saw them, so it only ever ends between whole blockquotes
+    let mut stream = Callouts::new(stream, self.callouts);
+
+    let written = emit(&mut stream);
+    drop(stream);
+    let (word_count, excerpt) = summary.into_inner().finish();
+
+    if let Some(e) = hook_error.or(image_error.into_inner()) {
+      return Err(e);
+    }
+    written?;
+
+    Ok(HTMLOutput {
+      toc: generate_toc(&toc),
+      content: String::new(),
+      warnings,
+      unresolved_links,
+      outline: outline.finish(),
+      word_count: word_count as u32,
+      reading_time_minutes: (word_count as u32).div_ceil(self.words_per_minute),
+      excerpt,
+      sections: sections.map(SectionsBuilder::finish),
+    })
+  }
+}
+
+impl Renderer {
+  /// Rewrites the target of a link or image through the `resolve_link` hook and the link
+  /// rules. Targets neither could resolve are recorded and kept as written
+  fn resolve_link<'e>(
+    &self,
+    dest: CowStr<'e>,
+    hooks: &dyn Hooks,
+    unresolved: &mut Vec<String>,
+    hook_error: &mut Option<Error>,
+  ) -> CowStr<'e> {
+    let resolved = match hooks.resolve_link(&dest) {
+      Ok(Some(resolved)) => Some(resolved),
+      Ok(None) => self.links.resolve(&dest),
+      Err(e) => {
+        hook_error.get_or_insert(e);
+        None
+      }
+    };
+    match resolved {
+      Some(ResolvedLink::Url(url)) => url.into(),
+      Some(ResolvedLink::Unresolved) => {
+        unresolved.push(dest.to_string());
+        dest
+      }
+      None => dest,
+    }
+  }
+
+  /// Swaps the opening tag of an external link for one that opens it in a new tab
+  fn decorate_link<'e>(&self, ev: Event<'e>) -> Event<'e> {
+    match (&self.external_links, &ev) {
+      (Some(external), Event::Start(Tag::Link(kind, dest, title)))
+        if *kind != LinkType::Email && external.is_external(dest) =>
+      {
+        Event::Html(external.start_tag(dest, title).into())
+      }
+      _ => ev,
+    }
+  }
+
+  /// Replaces dangerous URLs in safe mode
+  fn filter_url<'e>(&self, url: CowStr<'e>, image: bool) -> CowStr<'e> {
+    if self.raw_html.is_safe() {
+      safe_url(url, image)
+    } else {
+      url
+    }
+  }
+
+  /// Highlights a code block, going through the highlight cache when one is configured
+  fn highlight_block(
+    &self,
+    w: &mut String,
+    key: &str,
+    source: &str,
+    lang: &Option<&Lang>,
+  ) -> Result<(), HighlightError> {
+    let cache = match &self.cache {
+      Some(cache) if lang.and_then(|l| l.conf.as_ref()).is_some() => cache,
+      // Nothing worth caching, highlight_code bails out right away
+      _ => return highlight_code(w, key, source, lang, self.limits),
+    };
+
+    let cache_key = HighlightCache::key(key, source);
+    if let Some(markup) = cache.get(&cache_key) {
+      w.push_str(&markup);
+      return Ok(());
+    }
+    let start = w.len();
+    highlight_code(w, key, source, lang, self.limits)?;
+    cache.insert(cache_key, &w[start..]);
+    Ok(())
+  }
+}
+
+/// Lets pulldown-cmark's `io::Write` based writer fill a `fmt::Write` sink. The HTML writer
+/// only ever writes whole `str`s, so every buffer handed over is valid UTF-8
+struct FmtWriter<'w, W: fmt::Write>(&'w mut W);
+
+impl<W: fmt::Write> io::Write for FmtWriter<'_, W> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let s = std::str::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    self.0.write_str(s).map_err(io::Error::other)?;
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+/// A container for a diagram's source, left for a client side renderer to draw. Mermaid
+/// looks for `<pre class="mermaid">`, every other kind gets a generic `diagram` div
+fn diagram(kind: &str, source: &str) -> String {
+  let mut out = String::with_capacity(source.len() + 64);
+  if kind == "mermaid" {
+    out.push_str(r#"<pre class="mermaid">"#);
+    escape_html(&mut out, source).ok();
+    out.push_str("</pre>\n");
+  } else {
+    out.push_str(r#"<div class="diagram" data-kind=""#);
+    escape_html(&mut out, kind).ok();
+    out.push_str(r#"">"#);
+    escape_html(&mut out, source).ok();
+    out.push_str("</div>\n");
+  }
+  out
+}
+
+/// Replaces URLs that would run script when followed or loaded. Data URLs are only kept for
+/// images in common raster formats
+fn safe_url(url: CowStr<'_>, image: bool) -> CowStr<'_> {
+  // Browsers skip leading whitespace and control characters, and tabs and newlines anywhere
+  let normalized: String = url
+    .trim_start_matches(|c: char| c <= ' ')
+    .chars()
+    .filter(|c| !matches!(c, '\t' | '\n' | '\r'))
+    .take(32)
+    .collect::<String>()
+    .to_ascii_lowercase();
+  let scheme = match normalized.split_once(':') {
+    Some((scheme, _)) if !scheme.contains(['/', '?', '#']) => scheme,
+    _ => return url,
+  };
+  let safe = match scheme {
+    "javascript" | "vbscript" => false,
+    "data" => {
+      image
+        && ["png", "gif", "jpeg", "jpg", "webp"]
+          .iter()
+          .any(|format| normalized.starts_with(&format!("data:image/{format}")))
+    }
+    _ => true,
+  };
+  if safe {
+    url
+  } else {
+    "#".into()
+  }
+}
+
+/// Escapes a value for use in a double quoted attribute
+fn escape_attribute(value: &str) -> String {
+  let mut escaped = String::with_capacity(value.len());
+  escape_html(&mut escaped, value).ok();
+  escaped
+}
+
+fn theme_by_name(name: &str) -> Result<&'static Theme, Error> {
+  find_theme(name).ok_or_else(|| Error::InvalidConfig(format!("unknown theme {name:?}")))
+}
+
+/// The pulldown-cmark extensions to enable. Tables, footnotes, strikethrough and task lists
+/// are on unless turned off
+fn parser_options(options: &MarkdownOptions) -> Options {
+  let extensions = options.extensions.clone().unwrap_or_default();
+  let mut parser_options = Options::empty();
+  parser_options.set(Options::ENABLE_TABLES, extensions.tables.unwrap_or(true));
+  parser_options.set(
+    Options::ENABLE_FOOTNOTES,
+    extensions.footnotes.unwrap_or(true),
+  );
+  parser_options.set(
+    Options::ENABLE_STRIKETHROUGH,
+    extensions.strikethrough.unwrap_or(true),
+  );
+  parser_options.set(
+    Options::ENABLE_TASKLISTS,
+    extensions.tasklists.unwrap_or(true),
+  );
+  parser_options.set(
+    Options::ENABLE_SMART_PUNCTUATION,
+    extensions.smart_punctuation.unwrap_or(false),
+  );
+  parser_options.set(
+    Options::ENABLE_HEADING_ATTRIBUTES,
+    extensions.heading_attributes.unwrap_or(false),
+  );
+  parser_options
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  struct UpperSlugs;
+
+  impl Hooks for UpperSlugs {
+    fn slugify(&self, text: &str) -> Result<Option<String>, Error> {
+      Ok(Some(text.to_uppercase().replace(' ', "_")))
+    }
+  }
+
+  #[test]
+  fn test_renderer_validates_config() {
+    let config = RendererConfig {
+      theme: Some("no-such-theme".into()),
+      ..Default::default()
+    };
+    assert!(matches!(
+      Renderer::new(config),
+      Err(Error::InvalidConfig(_))
+    ));
+
+    let config = RendererConfig {
+      languages: Some(HashMap::from([("rs".into(), "rusty".into())])),
+      ..Default::default()
+    };
+    assert!(matches!(
+      Renderer::new(config),
+      Err(Error::InvalidConfig(_))
+    ));
+  }
+
+  #[test]
+  fn test_renderer_aliases_themes_and_hooks() {
+    let renderer = Renderer::new(RendererConfig {
+      theme: Some("github-light".into()),
+      dark_theme: Some("github-dark".into()),
+      languages: Some(HashMap::from([("rs".into(), "rust".into())])),
+      ..Default::default()
+    })
+    .unwrap();
+
+    let output = renderer
+      .render("# Hello world\n\n```rs\nfn main() {}\n```\n", &UpperSlugs)
+      .unwrap();
+    assert!(output.content.contains(r#"id="HELLO_WORLD""#));
+    assert!(output.content.contains(r#"data-lang="rs""#));
+    assert!(output.content.contains("<i class=hh"));
+
+    let css = renderer.stylesheet().unwrap();
+    assert!(css.contains(".code-block-inner .hh4 { color: #cf222e; }"));
+    assert!(css.contains("@media (prefers-color-scheme: dark)"));
+  }
+
+  #[test]
+  fn test_streaming_matches_buffered_render() {
+    let renderer = Renderer::from_options(Default::default()).unwrap();
+    let input = "# Title\n\nSome text\n\n```rust\nfn main() {}\n```\n";
+    let buffered = renderer.render(input, &NoHooks).unwrap();
+
+    let mut bytes = Vec::new();
+    let streamed = renderer
+      .render_to_writer(input, &NoHooks, &mut bytes)
+      .unwrap();
+    assert_eq!(String::from_utf8(bytes).unwrap(), buffered.content);
+    assert_eq!(streamed.toc, buffered.toc);
+    assert!(streamed.content.is_empty());
+
+    let mut text = String::new();
+    renderer.render_to_fmt(input, &NoHooks, &mut text).unwrap();
+    assert_eq!(text, buffered.content);
+  }
+
+  #[test]
+  fn test_renderer_reuses_cached_highlights() {
+    let renderer = Renderer::from_options(MarkdownOptions {
+      highlight_cache: Some(Default::default()),
+      ..Default::default()
+    })
+    .unwrap();
+    let input = "```rust\nlet answer = 42;\n```\n";
+    let first = renderer.render(input, &NoHooks).unwrap();
+    let cache = renderer.cache.as_ref().unwrap();
+    assert!(cache
+      .get(&HighlightCache::key("rust", "let answer = 42;\n"))
+      .is_some());
+    let second = renderer.render(input, &NoHooks).unwrap();
+    assert_eq!(first.content, second.content);
+
+    // One-shot renders with the same options pick up where this one left off
+    let options = MarkdownOptions {
+      highlight_cache: Some(Default::default()),
+      ..Default::default()
+    };
+    let again = Renderer::from_options(options).unwrap();
+    assert!(Arc::ptr_eq(cache, again.cache.as_ref().unwrap()));
+  }
+
+  #[test]
+  fn test_unknown_languages_are_warned_about() {
+    let renderer = Renderer::from_options(Default::default()).unwrap();
+    let input = "```klingon
+qaStaH nuq?
+```
+
+```shell
+$ ls
+```
+
+    plain
+";
+    let output = renderer.render(input, &NoHooks).unwrap();
+    assert!(output.content.contains("qaStaH nuq?"));
+    // Shell sessions have no grammar by design, and unlabeled blocks have no language
+    assert_eq!(output.warnings.len(), 1);
+    assert_eq!(output.warnings[0].code, "UNKNOWN_LANGUAGE");
+    assert!(output.warnings[0].message.contains("klingon"));
+
+    struct Klingon;
+    impl Hooks for Klingon {
+      fn highlight(&self, _lang: &str, source: &str) -> Result<Option<String>, Error> {
+        Ok(Some(source.to_uppercase()))
+      }
+    }
+    let output = renderer.render(input, &Klingon).unwrap();
+    assert!(output.content.contains("QASTAH NUQ?"));
+    assert!(output.warnings.is_empty());
+  }
+
+  #[test]
+  fn test_blocks_over_the_limits_are_escaped() {
+    let renderer = Renderer::from_options(MarkdownOptions {
+      max_highlight_bytes: Some(64),
+      ..Default::default()
+    })
+    .unwrap();
+    let long = "let x = 1 < 2;\n".repeat(10);
+    let input = format!("```rust\n{long}```\n\n```rust\nlet y = 3;\n```\n");
+    let output = renderer.render(&input, &NoHooks).unwrap();
+    assert!(output.content.contains("let x = 1 &lt; 2;"));
+    assert!(output.content.contains("<i class=hh"));
+    assert_eq!(output.warnings.len(), 1);
+    assert_eq!(output.warnings[0].code, "TOO_LARGE");
+
+    let renderer = Renderer::from_options(MarkdownOptions {
+      highlight_timeout_ms: Some(1),
+      ..Default::default()
+    })
+    .unwrap();
+    let huge = "fn f() { let x = [1, 2, 3].iter().map(|y| y * 2).sum::<i32>(); }\n".repeat(50_000);
+    let small = "```rust\nlet y = 3;\n```\n";
+    let output = renderer
+      .render(&format!("```rust\n{huge}```\n\n{small}"), &NoHooks)
+      .unwrap();
+    assert_eq!(output.warnings.len(), 1);
+    assert_eq!(output.warnings[0].code, "TIMED_OUT");
+    // The cancelled parse doesn't leak into the next block
+    let expected = renderer.render(small, &NoHooks).unwrap();
+    assert!(expected.content.contains("<i class=hh"));
+    assert!(output.content.ends_with(&expected.content));
+  }
+
+  #[test]
+  fn test_safe_mode_escapes_html_and_filters_urls() {
+    let input = "<script>alert(1)</script>\n\n[a](javascript:alert(2)) ![b](data:text/html,x) \
+                 ![c](data:image/png;base64,AA) [d](/docs) <span>hi</span>\n\n\
+                 ```x\" onmouseover=\"alert(3)\nx\n```\n";
+
+    let escaped = Renderer::from_options(MarkdownOptions {
+      raw_html: Some("escape".into()),
+      ..Default::default()
+    })
+    .unwrap()
+    .render(input, &NoHooks)
+    .unwrap();
+    assert!(escaped
+      .content
+      .contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+    assert!(escaped.content.contains(r##"<a href="#">a</a>"##));
+    assert!(escaped.content.contains(r##"<img src="#" alt="b" />"##));
+    assert!(escaped
+      .content
+      .contains(r#"src="data:image/png;base64,AA""#));
+    assert!(escaped.content.contains(r#"<a href="/docs">d</a>"#));
+    assert!(escaped
+      .content
+      .contains(r#"data-lang="x&quot; onmouseover=&quot;alert(3)""#));
+
+    let stripped = Renderer::from_options(MarkdownOptions {
+      raw_html: Some("strip".into()),
+      ..Default::default()
+    })
+    .unwrap()
+    .render(input, &NoHooks)
+    .unwrap();
+    assert!(!stripped.content.contains("script"));
+    assert!(!stripped.content.contains("span"));
+    assert!(stripped.content.contains(" hi"));
+
+    assert!(matches!(
+      Renderer::from_options(MarkdownOptions {
+        raw_html: Some("sanitize".into()),
+        ..Default::default()
+      }),
+      Err(Error::InvalidConfig(_))
+    ));
+  }
+
+  #[test]
+  fn test_headings_keep_inline_formatting() {
+    let renderer = Renderer::from_options(Default::default()).unwrap();
+    let input = "# `Vec<T>` & *friends*\n\n## See [the docs](/docs) ~~now~~\n";
+    let output = renderer.render(input, &NoHooks).unwrap();
+    assert!(output.content.contains(
+      "<code>Vec&lt;T&gt;</code> &amp; <em>friends</em>\n                            </a>"
+    ));
+    // The anchor sits beside a heading with a link, rather than around it
+    assert!(output.content.contains(
+      r##"<a id="see-the-docs-now" class="anchor" href="#see-the-docs-now" aria-hidden="true"></a>"##
+    ));
+    assert!(output
+      .content
+      .contains(r#"See <a href="/docs">the docs</a> <del>now</del>"#));
+
+    let toc = output.toc.unwrap();
+    assert!(toc.contains(
+      r##"<a href="#vec-t-friends"><code>Vec&lt;T&gt;</code> &amp; <em>friends</em></a>"##
+    ));
+    assert!(toc.contains(r##"<a href="#see-the-docs-now">See the docs <del>now</del></a>"##));
+
+    // Inline HTML in a heading is kept as markup, but never makes it into the slug
+    let output = renderer.render("# Hello <sup>1</sup>\n", &NoHooks).unwrap();
+    assert!(output.content.contains(
+      r##"<a id="hello-1" class="anchor" href="#hello-1">
+                                Hello <sup>1</sup>
+                            </a>"##
+    ));
+    assert_eq!(output.outline.headings[0].text, "Hello 1");
+    assert!(output
+      .toc
+      .unwrap()
+      .contains(r##"<a href="#hello-1">Hello <sup>1</sup></a>"##));
+
+    // Without backticks, `<T>` is inline HTML to the parser, shown as text when escaped
+    let escaping = Renderer::from_options(MarkdownOptions {
+      raw_html: Some("escape".into()),
+      ..Default::default()
+    })
+    .unwrap();
+    let output = escaping.render("# Vec<T> & friends\n", &NoHooks).unwrap();
+    assert!(output.content.contains(
+      r##"<a id="vec-friends" class="anchor" href="#vec-friends">
+                                Vec&lt;T&gt; &amp; friends
+                            </a>"##
+    ));
+    assert!(output
+      .toc
+      .unwrap()
+      .contains(r##"<a href="#vec-friends">Vec&lt;T&gt; &amp; friends</a>"##));
+  }
+
+  #[test]
+  fn test_links_are_resolved() {
+    struct Pages;
+    impl Hooks for Pages {
+      fn resolve_link(&self, url: &str) -> Result<Option<ResolvedLink>, Error> {
+        Ok(match url {
+          "missing.md" => Some(ResolvedLink::Unresolved),
+          "home" => Some(ResolvedLink::Url("/".into())),
+          _ => None,
+        })
+      }
+    }
+
+    let renderer = Renderer::from_options(MarkdownOptions {
+      links: Some(crate::LinkOptions {
+        path: Some("/tutorial/start".into()),
+        strip_md_extension: Some(true),
+        ..Default::default()
+      }),
+      ..Default::default()
+    })
+    .unwrap();
+    let input = "[a](../guide/intro.md#setup) ![b](img/b.png) [c](missing.md) [d](home) \
+      [e](../../up.md) <me@example.com>\n";
+    let output = renderer.render(input, &Pages).unwrap();
+    assert!(output
+      .content
+      .contains(r##"<a href="/guide/intro#setup">a</a>"##));
+    assert!(output.content.contains(r#"<img src="/tutorial/img/b.png""#));
+    assert!(output.content.contains(r#"<a href="missing.md">c</a>"#));
+    assert!(output.content.contains(r#"<a href="/">d</a>"#));
+    assert!(output
+      .content
+      .contains(r#"<a href="mailto:me@example.com">"#));
+    assert_eq!(output.unresolved_links, ["missing.md", "../../up.md"]);
+  }
+
+  #[test]
+  fn test_external_links_are_decorated() {
+    let renderer = Renderer::from_options(MarkdownOptions {
+      external_links: Some(crate::ExternalLinkOptions {
+        site_origin: Some("https://example.com".into()),
+        class: Some("external".into()),
+      }),
+      ..Default::default()
+    })
+    .unwrap();
+    let input = "# [Rust](https://rust-lang.org)\n\n[about](https://example.com/about) \
+      [docs](https://docs.rs \"API docs\") [home](/)\n";
+    let output = renderer.render(input, &NoHooks).unwrap();
+    assert!(output.content.contains(
+      r#"<a href="https://rust-lang.org" class="external" rel="noopener noreferrer nofollow" target="_blank">Rust</a>"#
+    ));
+    assert!(output.content.contains(
+      r#"<a href="https://docs.rs" title="API docs" class="external" rel="noopener noreferrer nofollow" target="_blank">docs</a>"#
+    ));
+    assert!(output
+      .content
+      .contains(r#"<a href="https://example.com/about">about</a>"#));
+    assert!(output.content.contains(r#"<a href="/">home</a>"#));
+    // The table of contents only keeps the text of links
+    assert!(output
+      .toc
+      .unwrap()
+      .contains(r##"<a href="#rust">Rust</a>"##));
+  }
+
+  #[test]
+  fn test_outline_collects_what_the_renderer_saw() {
+    let renderer = Renderer::from_options(MarkdownOptions {
+      default_language: Some("rust".into()),
+      ..Default::default()
+    })
+    .unwrap();
+    let input =
+      "# Intro to `femark`\n\n> ## Quoted\n\nSee [the *guide*](/guide) and <me@example.com>.\
+      [^note] [^missing]\n\n[![Logo](/logo.png \"Our logo\")](/)\n\n    let x = 1;\n    x\n\n\
+      [^note]: A note.\n";
+    let outline = renderer.render(input, &NoHooks).unwrap().outline;
+
+    assert_eq!(outline.headings.len(), 1);
+    assert_eq!(outline.headings[0].slug, "intro-to-femark");
+    assert_eq!(outline.headings[0].text, "Intro to femark");
+
+    let links: Vec<_> = outline
+      .links
+      .iter()
+      .map(|link| (link.url.as_str(), link.text.as_str()))
+      .collect();
+    assert_eq!(
+      links,
+      [
+        ("/guide", "the guide"),
+        ("mailto:me@example.com", "me@example.com"),
+        ("/", "Logo"),
+      ]
+    );
+    assert_eq!(outline.images[0].src, "/logo.png");
+    assert_eq!(outline.images[0].alt, "Logo");
+    assert_eq!(outline.images[0].title.as_deref(), Some("Our logo"));
+
+    let footnotes: Vec<_> = outline
+      .footnotes
+      .iter()
+      .map(|f| (f.label.as_str(), f.references, f.defined))
+      .collect();
+    assert_eq!(footnotes, [("note", 1, true), ("missing", 1, false)]);
+
+    assert_eq!(outline.code_blocks.len(), 1);
+    assert_eq!(outline.code_blocks[0].lang, "rust");
+    assert_eq!(outline.code_blocks[0].lines, 2);
+  }
+
+  #[test]
+  fn test_reading_time_and_excerpt() {
+    let renderer = Renderer::from_options(MarkdownOptions {
+      words_per_minute: Some(4),
+      raw_html: Some("escape".into()),
+      ..Default::default()
+    })
+    .unwrap();
+    let input =
+      "# Title\n\nA short *intro*.\n\n<!-- more -->\n\n```rust\nfn main() {}\n```\n\nThe end.\n";
+    let output = renderer.render(input, &NoHooks).unwrap();
+    assert_eq!(output.word_count, 6);
+    assert_eq!(output.reading_time_minutes, 2);
+    // The marker is dropped, even when other HTML is escaped
+    assert!(!output.content.contains("more"));
+    assert_eq!(output.excerpt.text, "Title A short intro.");
+    assert!(output
+      .excerpt
+      .html
+      .ends_with("<p>A short <em>intro</em>.</p>\n"));
+    assert!(output.excerpt.html.contains(r#"<a id="title""#));
+  }
+
+  #[test]
+  fn test_search_sections_follow_the_headings() {
+    let input =
+      "Preface text.\n\n# Getting `started`\n\nInstall it:\n\n```sh\nnpm i femark\n```\n\n\
+      > ## Quoted\n\n## Next *steps*\nRead on.\n";
+    let plain = Renderer::from_options(Default::default())
+      .unwrap()
+      .render(input, &NoHooks)
+      .unwrap();
+    assert!(plain.sections.is_none());
+
+    let renderer = Renderer::from_options(MarkdownOptions {
+      search_index: Some(crate::SearchIndexOptions {
+        include_code: Some(true),
+      }),
+      ..Default::default()
+    })
+    .unwrap();
+    let sections = renderer.render(input, &NoHooks).unwrap().sections.unwrap();
+    let sections: Vec<_> = sections
+      .iter()
+      .map(|s| (s.slug.as_str(), s.title.as_str(), s.level, s.text.as_str()))
+      .collect();
+    assert_eq!(
+      sections,
+      [
+        ("", "", 0, "Preface text."),
+        (
+          "getting-started",
+          "Getting started",
+          1,
+          "Install it: npm i femark Quoted"
+        ),
+        ("next-steps", "Next steps", 2, "Read on."),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_images_get_attributes_and_figures() {
+    struct Sizes;
+    impl Hooks for Sizes {
+      fn image_info(&self, src: &str) -> Result<Option<ImageInfo>, Error> {
+        Ok((src == "/hooked.png").then(|| ImageInfo {
+          width: Some(10),
+          height: Some(20),
+          ..Default::default()
+        }))
+      }
+    }
+
+    let renderer = Renderer::from_options(MarkdownOptions {
+      images: Some(crate::ImageOptions {
+        lazy: Some(true),
+        async_decoding: Some(true),
+        figures: Some(true),
+        manifest: Some(HashMap::from([(
+          "/cat.jpg".to_string(),
+          ImageInfo {
+            width: Some(800),
+            height: Some(600),
+            srcset: Some("/cat-2x.jpg 2x".into()),
+            sizes: None,
+          },
+        )])),
+      }),
+      ..Default::default()
+    })
+    .unwrap();
+    let input = "![A *cat*](/cat.jpg \"Our cat\")\n\nInline ![x](/hooked.png \"t\") image\n\n![untitled](/u.png)\n";
+    let output = renderer.render(input, &Sizes).unwrap();
+    assert!(output.content.contains(
+      r#"<figure><img src="/cat.jpg" alt="A cat" width="800" height="600" srcset="/cat-2x.jpg 2x" loading="lazy" decoding="async" /><figcaption>Our cat</figcaption></figure>"#
+    ));
+    assert!(output.content.contains(
+      r#"<p>Inline <img src="/hooked.png" alt="x" title="t" width="10" height="20" loading="lazy" decoding="async" /> image</p>"#
+    ));
+    assert!(output
+      .content
+      .contains(r#"<p><img src="/u.png" alt="untitled" loading="lazy" decoding="async" /></p>"#));
+    assert_eq!(output.outline.images.len(), 3);
+
+    // Without options, images render as pulldown-cmark writes them
+    let plain = Renderer::from_options(Default::default())
+      .unwrap()
+      .render(input, &Sizes)
+      .unwrap();
+    assert!(plain
+      .content
+      .contains(r#"<p><img src="/u.png" alt="untitled" /></p>"#));
+  }
+
+  #[test]
+  fn test_callouts_keep_headings_out_of_the_toc() {
+    let renderer = Renderer::from_options(MarkdownOptions {
+      callouts: Some(true),
+      excerpt_words: Some(2),
+      ..Default::default()
+    })
+    .unwrap();
+    let input =
+      "> [!WARNING]\n> ## Hidden\n> Careful here.\n\n:::info\n## Also hidden\n:::\n\n## Shown\n";
+    let output = renderer.render(input, &NoHooks).unwrap();
+    assert!(output
+      .content
+      .starts_with("<aside class=\"callout callout-warning\">\n<p class=\"callout-title\">"));
+    assert!(output
+      .content
+      .contains("<aside class=\"callout callout-info\">"));
+    assert_eq!(output.outline.headings.len(), 1);
+    assert_eq!(output.outline.headings[0].slug, "shown");
+    // The excerpt ends after the whole callout
+    assert!(output
+      .excerpt
+      .html
+      .ends_with("<p>Careful here.</p>\n</aside>\n"));
+    // Markers aren't part of the text
+    assert_eq!(output.excerpt.text, "Hidden Careful");
+    assert_eq!(output.word_count, 6);
+
+    // Headings after a nested quote are still in the outer one
+    let output = renderer
+      .render("> outer\n> > inner\n>\n> ## Still in outer\n", &NoHooks)
+      .unwrap();
+    assert!(output.outline.headings.is_empty());
+    assert!(output.toc.is_none());
+
+    let output = renderer
+      .render(
+        ":::tip Pro tip\nBody text\n:::\n\n> [!NOPE] kept\n",
+        &NoHooks,
+      )
+      .unwrap();
+    assert_eq!(output.excerpt.text, "Pro tip");
+    // Only real markers are left out
+    assert_eq!(output.word_count, 6);
+  }
+
+  #[test]
+  fn test_math_formulas() {
+    let renderer = Renderer::from_options(MarkdownOptions {
+      math: Some(true),
+      search_index: Some(Default::default()),
+      ..Default::default()
+    })
+    .unwrap();
+    let input = "## Where $x_i$ is\n\nSo $a*b*c$ holds.\n\n```math\nE = mc^2\n```\n";
+    let output = renderer.render(input, &NoHooks).unwrap();
+    assert!(output
+      .content
+      .contains(r#"<span class="math math-inline">a*b*c</span> holds."#));
+    assert!(output
+      .content
+      .contains("<p><span class=\"math math-display\">E = mc^2</span></p>"));
+    assert_eq!(output.outline.headings[0].text, "Where x_i is");
+    assert!(output
+      .toc
+      .unwrap()
+      .contains(r#"<span class="math math-inline">x_i</span>"#));
+    let sections = output.sections.unwrap();
+    assert_eq!(sections[0].text, "So a*b*c holds.");
+
+    // Off by default, so dollar signs stay text
+    let plain = Renderer::from_options(Default::default())
+      .unwrap()
+      .render("$a*b*c$", &NoHooks)
+      .unwrap();
+    assert_eq!(plain.content, "<p>$a<em>b</em>c$</p>\n");
+  }
+
+  #[test]
+  fn test_math_leaves_link_targets_alone() {
+    let renderer = Renderer::from_options(MarkdownOptions {
+      math: Some(true),
+      ..Default::default()
+    })
+    .unwrap();
+    let url = "https://api.example.com/items?$filter=x&$top=5";
+    let output = renderer
+      .render(
+        &format!("[the API]({url}) and <https://x.com/?$a$>\n"),
+        &NoHooks,
+      )
+      .unwrap();
+    assert!(output
+      .content
+      .contains(r#"<a href="https://api.example.com/items?$filter=x&amp;$top=5">the API</a>"#));
+    assert!(output
+      .content
+      .contains(r#"<a href="https://x.com/?$a$">https://x.com/?$a$</a>"#));
+    assert_eq!(output.outline.links[0].url, url);
+    assert_eq!(output.outline.links[1].text, "https://x.com/?$a$");
+  }
+
+  #[test]
+  fn test_math_skips_code_spans() {
+    let renderer = Renderer::from_options(MarkdownOptions {
+      math: Some(true),
+      ..Default::default()
+    })
+    .unwrap();
+    let output = renderer
+      .render("Price $5 and `$x$` text $a_b$.", &NoHooks)
+      .unwrap();
+    assert_eq!(
+      output.content,
+      "<p>Price $5 and <code>$x$</code> text <span class=\"math math-inline\">a_b</span>.</p>\n"
+    );
+  }
+
+  #[test]
+  fn test_diagrams_skip_highlighting() {
+    let renderer = Renderer::from_options(MarkdownOptions {
+      diagrams: Some(vec!["mermaid".into(), "dot".into()]),
+      ..Default::default()
+    })
+    .unwrap();
+    let input = "```mermaid\ngraph TD; A-->B\n```\n\n```dot\ndigraph { a -> b }\n```\n";
+    let output = renderer.render(input, &NoHooks).unwrap();
+    assert_eq!(
+      output.content,
+      "<pre class=\"mermaid\">graph TD; A--&gt;B\n</pre>\n<div class=\"diagram\" data-kind=\"dot\">digraph { a -&gt; b }\n</div>\n"
+    );
+    assert!(output.warnings.is_empty());
+    assert_eq!(output.outline.code_blocks.len(), 2);
+  }
+
+  #[test]
+  fn test_heading_attributes_set_id_and_class() {
+    let renderer = Renderer::from_options(MarkdownOptions {
+      extensions: Some(crate::MarkdownExtensions {
+        heading_attributes: Some(true),
+        ..Default::default()
+      }),
+      ..Default::default()
+    })
+    .unwrap();
+    let output = renderer
+      .render("# Title {#custom .cls .wide}\n\n## Plain\n", &NoHooks)
+      .unwrap();
+    assert!(output.content.contains(r#"<h1 class="cls wide">"#));
+    assert!(output
+      .content
+      .contains(r##"<a id="custom" class="anchor" href="#custom">"##));
+    assert!(output.content.contains("<h2>"));
+    assert_eq!(output.outline.headings[0].slug, "custom");
+    assert_eq!(output.outline.headings[1].slug, "plain");
+  }
+}
@@ -0,0 +1,83 @@
+use crate::summary::separates_words;
+use pulldown_cmark::{Event, Tag};
+use serde::{Deserialize, Serialize};
+
+/// A stretch of a document between two headings, as plain text for a search index
+#[cfg_attr(feature = "node", napi(object))]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchSection {
+  /// The anchor of the heading, for deep links to the section. Empty for the text before
+  /// the first heading
+  pub slug: String,
+  /// The heading's plain text
+  pub title: String,
+  /// 1 through 6, or 0 for the text before the first heading
+  pub level: u8,
+  pub text: String,
+}
+
+/// Splits a document into sections while the renderer walks it
+pub(crate) struct SectionsBuilder {
+  include_code: bool,
+  sections: Vec<SearchSection>,
+  text: String,
+  in_code: bool,
+}
+
+impl SectionsBuilder {
+  pub(crate) fn new(include_code: bool) -> Self {
+    Self {
+      include_code,
+      sections: Vec::new(),
+      text: String::new(),
+      in_code: false,
+    }
+  }
+
+  /// Takes in a source event outside of a heading
+  pub(crate) fn event(&mut self, ev: &Event) {
+    match ev {
+      Event::Start(Tag::CodeBlock(_)) => self.in_code = true,
+      Event::End(Tag::CodeBlock(_)) => {
+        self.in_code = false;
+        self.text.push(' ');
+      }
+      Event::Text(text) if !self.in_code || self.include_code => self.text.push_str(text),
+      Event::Code(text) => self.text.push_str(text),
+      ev if separates_words(ev) => self.text.push(' '),
+      _ => {}
+    }
+  }
+
+  /// Starts the section of a heading the renderer gave an anchor
+  pub(crate) fn heading(&mut self, level: u8, slug: &str, title: &str) {
+    self.close();
+    self.sections.push(SearchSection {
+      slug: slug.into(),
+      title: title.split_whitespace().collect::<Vec<_>>().join(" "),
+      level,
+      text: String::new(),
+    });
+  }
+
+  fn close(&mut self) {
+    let text = self.text.split_whitespace().collect::<Vec<_>>().join(" ");
+    self.text.clear();
+    match self.sections.last_mut() {
+      Some(section) => section.text = text,
+      // Text before the first heading gets a section of its own
+      None if !text.is_empty() => self.sections.push(SearchSection {
+        slug: String::new(),
+        title: String::new(),
+        level: 0,
+        text,
+      }),
+      None => {}
+    }
+  }
+
+  pub(crate) fn finish(mut self) -> Vec<SearchSection> {
+    self.close();
+    self.sections
+  }
+}
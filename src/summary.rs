@@ -0,0 +1,239 @@
+use crate::callouts::Callouts;
+use pulldown_cmark::{html, Event, Tag};
+use serde::{Deserialize, Serialize};
+
+/// Words in the excerpt when no `<!-- more -->` marker ends it earlier
+pub const DEFAULT_EXCERPT_WORDS: u32 = 50;
+/// Reading speed the reading time is estimated with
+pub const DEFAULT_WORDS_PER_MINUTE: u32 = 200;
+/// Rendered bytes the excerpt's HTML may hold. A block that would go past it, like a large
+/// highlighted code block, ends the excerpt before it, so short documents don't keep their
+/// whole event stream around
+const MAX_EXCERPT_BYTES: usize = 16 * 1024;
+
+/// The start of a document, for post listings and link previews
+#[cfg_attr(feature = "node", napi(object))]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Excerpt {
+  /// The first words of the prose, or all of it up to a `<!-- more -->` marker
+  pub text: String,
+  /// The rendered blocks up to the marker, or up to the block that reaches the word limit.
+  /// Stops short of a block that would take it past 16 KiB
+  pub html: String,
+}
+
+/// Whether an HTML event is the marker that ends the excerpt
+pub(crate) fn is_more_marker(html: &str) -> bool {
+  matches!(html.trim(), "<!-- more -->" | "<!--more-->")
+}
+
+/// Whether the text on either side of an event belongs to different words, like at line
+/// breaks and the end of a paragraph
+pub(crate) fn separates_words(ev: &Event) -> bool {
+  matches!(
+    ev,
+    Event::SoftBreak
+      | Event::HardBreak
+      | Event::End(
+        Tag::Paragraph
+          | Tag::Heading(..)
+          | Tag::Item
+          | Tag::TableCell
+          | Tag::FootnoteDefinition(_)
+          | Tag::CodeBlock(_)
+      )
+  )
+}
+
+fn is_inline(tag: &Tag) -> bool {
+  matches!(
+    tag,
+    Tag::Emphasis | Tag::Strong | Tag::Strikethrough | Tag::Link(..) | Tag::Image(..)
+  )
+}
+
+/// Counts the words of a document and cuts its excerpt while the renderer walks it. Prose
+/// is seen through `event` before rendering, the excerpt's HTML through `emitted` after
+pub(crate) struct SummaryBuilder<'a> {
+  excerpt_words: usize,
+  callouts: bool,
+  // The prose so far, with blocks separated by spaces
+  text: String,
+  in_code: bool,
+  in_image: usize,
+  // Words before the marker, once it was seen
+  marker: Option<usize>,
+  // Top level blocks of the excerpt, until it is closed
+  events: Option<Vec<Event<'a>>>,
+  // Where the current top level block starts in `events`, and the text they hold
+  block_start: usize,
+  bytes: usize,
+  depth: usize,
+  html: String,
+}
+
+impl<'a> SummaryBuilder<'a> {
+  pub(crate) fn new(excerpt_words: usize, callouts: bool) -> Self {
+    Self {
+      excerpt_words,
+      callouts,
+      text: String::new(),
+      in_code: false,
+      in_image: 0,
+      marker: None,
+      events: Some(Vec::new()),
+      block_start: 0,
+      bytes: 0,
+      depth: 0,
+      html: String::new(),
+    }
+  }
+
+  /// Takes in a source event, before it is rendered
+  pub(crate) fn event(&mut self, ev: &Event) {
+    match ev {
+      Event::Start(Tag::CodeBlock(_)) => self.in_code = true,
+      Event::End(Tag::CodeBlock(_)) => self.in_code = false,
+      // Alt text describes the image rather than being part of the prose
+      Event::Start(Tag::Image(..)) => self.in_image += 1,
+      Event::End(Tag::Image(..)) => self.in_image -= 1,
+      // Inline code is part of the sentence around it, unlike code blocks
+      Event::Text(text) | Event::Code(text) if !self.in_code && self.in_image == 0 => {
+        self.text.push_str(text)
+      }
+      ev if separates_words(ev) => self.text.push(' '),
+      _ => {}
+    }
+  }
+
+  /// Notes a `<!-- more -->` marker, which ends the excerpt after the block it appears in
+  pub(crate) fn marker(&mut self) {
+    if self.marker.is_none() {
+      self.marker = Some(self.words());
+    }
+  }
+
+  /// Takes in a rendered event, collecting the excerpt's blocks until it is complete
+  pub(crate) fn emitted(&mut self, ev: &Event<'a>) {
+    let events = match self.events.as_mut() {
+      Some(events) => events,
+      None => return,
+    };
+    // A marker between blocks ends the excerpt before the next one
+    if self.depth == 0 && self.marker.is_some() {
+      self.close();
+      return;
+    }
+
+    if self.depth == 0 {
+      self.block_start = events.len();
+    }
+    self.bytes += match ev {
+      Event::Text(text) | Event::Code(text) | Event::Html(text) => text.len(),
+      _ => 0,
+    };
+    if self.bytes > MAX_EXCERPT_BYTES {
+      events.truncate(self.block_start);
+      self.close();
+      return;
+    }
+    events.push(ev.clone());
+    // Only blocks count, an external link's opening tag is rendered as HTML already
+    match ev {
+      Event::Start(tag) if !is_inline(tag) => self.depth += 1,
+      Event::End(tag) if !is_inline(tag) => self.depth -= 1,
+      _ => {}
+    }
+    if self.depth == 0 && (self.marker.is_some() || self.words() >= self.excerpt_words) {
+      self.close();
+    }
+  }
+
+  fn close(&mut self) {
+    if let Some(events) = self.events.take() {
+      html::push_html(
+        &mut self.html,
+        Callouts::new(events.into_iter(), self.callouts),
+      );
+    }
+  }
+
+  fn words(&self) -> usize {
+    self.text.split_whitespace().count()
+  }
+
+  /// The word count, and the excerpt
+  pub(crate) fn finish(mut self) -> (usize, Excerpt) {
+    self.close();
+    let words = self.words();
+    let text = self
+      .text
+      .split_whitespace()
+      .take(self.marker.unwrap_or(self.excerpt_words))
+      .collect::<Vec<_>>()
+      .join(" ");
+    (
+      words,
+      Excerpt {
+        text,
+        html: self.html,
+      },
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pulldown_cmark::Parser;
+
+  fn summarize(input: &str, excerpt_words: usize) -> (usize, Excerpt) {
+    let mut summary = SummaryBuilder::new(excerpt_words, false);
+    for ev in Parser::new(input) {
+      summary.event(&ev);
+      match &ev {
+        Event::Html(html) if is_more_marker(html) => summary.marker(),
+        _ => summary.emitted(&ev),
+      }
+    }
+    summary.finish()
+  }
+
+  #[test]
+  fn test_words_exclude_code_and_alt_text() {
+    let input =
+      "# Two words\n\nOne `two` three.\n\n```\nnot counted\n```\n\n![not either](x.png)\n";
+    assert_eq!(summarize(input, 50).0, 5);
+  }
+
+  #[test]
+  fn test_excerpt_ends_at_whole_blocks() {
+    let input = "First block here.\n\nSecond block.\n\nThird.\n";
+    let (words, excerpt) = summarize(input, 4);
+    assert_eq!(words, 6);
+    assert_eq!(excerpt.text, "First block here. Second");
+    assert_eq!(
+      excerpt.html,
+      "<p>First block here.</p>\n<p>Second block.</p>\n"
+    );
+  }
+
+  #[test]
+  fn test_more_marker_ends_the_excerpt() {
+    let input = "Intro.\n\nStill intro.\n\n<!-- more -->\n\nThe rest of the post.\n";
+    let (words, excerpt) = summarize(input, 50);
+    assert_eq!(words, 8);
+    assert_eq!(excerpt.text, "Intro. Still intro.");
+    assert_eq!(excerpt.html, "<p>Intro.</p>\n<p>Still intro.</p>\n");
+  }
+
+  #[test]
+  fn test_excerpt_html_is_bounded() {
+    let code = "x".repeat(MAX_EXCERPT_BYTES);
+    let input = format!("Short intro.\n\n```\n{code}\n```\n\nAfter.\n");
+    let (words, excerpt) = summarize(&input, 50);
+    assert_eq!(words, 3);
+    assert_eq!(excerpt.text, "Short intro. After.");
+    assert_eq!(excerpt.html, "<p>Short intro.</p>\n");
+  }
+}
@@ -0,0 +1,117 @@
+use crate::HIGHLIGHT_NAMES;
+use std::fmt::Write;
+
+/// A color scheme for highlighted code blocks. Each style is keyed by one of the
+/// `HIGHLIGHT_NAMES`, and ends up as a rule for the matching `hhN` class
+pub struct Theme {
+  pub name: &'static str,
+  background: &'static str,
+  foreground: &'static str,
+  styles: &'static [(&'static str, &'static str)],
+}
+
+/// Themes that ship with femark
+pub static THEMES: &[Theme] = &[
+  Theme {
+    name: "basic",
+    background: "inherit",
+    foreground: "inherit",
+    styles: &[
+      ("keyword", "color: purple"),
+      ("function", "color: blue"),
+      ("type", "color: pink"),
+      ("string", "color: green"),
+      ("operator", "color: gray"),
+      ("comment", "color: lightgray"),
+    ],
+  },
+  Theme {
+    name: "github-light",
+    background: "#f6f8fa",
+    foreground: "#24292f",
+    styles: &[
+      ("attribute", "color: #0550ae"),
+      ("constant", "color: #0550ae"),
+      ("function.builtin", "color: #8250df"),
+      ("function", "color: #8250df"),
+      ("keyword", "color: #cf222e"),
+      ("operator", "color: #cf222e"),
+      ("property", "color: #0550ae"),
+      ("string", "color: #0a3069"),
+      ("string.special", "color: #0a3069"),
+      ("tag", "color: #116329"),
+      ("type", "color: #953800"),
+      ("type.builtin", "color: #953800"),
+      ("variable.builtin", "color: #0550ae"),
+      ("variable.parameter", "color: #953800"),
+      ("comment", "color: #6e7781; font-style: italic"),
+      ("macro", "color: #8250df"),
+      ("label", "color: #0550ae"),
+    ],
+  },
+  Theme {
+    name: "github-dark",
+    background: "#161b22",
+    foreground: "#c9d1d9",
+    styles: &[
+      ("attribute", "color: #79c0ff"),
+      ("constant", "color: #79c0ff"),
+      ("function.builtin", "color: #d2a8ff"),
+      ("function", "color: #d2a8ff"),
+      ("keyword", "color: #ff7b72"),
+      ("operator", "color: #ff7b72"),
+      ("property", "color: #79c0ff"),
+      ("string", "color: #a5d6ff"),
+      ("string.special", "color: #a5d6ff"),
+      ("tag", "color: #7ee787"),
+      ("type", "color: #ffa657"),
+      ("type.builtin", "color: #ffa657"),
+      ("variable.builtin", "color: #79c0ff"),
+      ("variable.parameter", "color: #ffa657"),
+      ("comment", "color: #8b949e; font-style: italic"),
+      ("macro", "color: #d2a8ff"),
+      ("label", "color: #79c0ff"),
+    ],
+  },
+];
+
+/// Looks up a theme by name
+pub fn find_theme(name: &str) -> Option<&'static Theme> {
+  THEMES.iter().find(|theme| theme.name == name)
+}
+
+impl Theme {
+  /// Writes the CSS rules for this theme, scoped to code blocks
+  pub fn write_css(&self, w: &mut String) {
+    writeln!(
+      w,
+      ".code-block-inner {{ background: {}; color: {}; }}",
+      self.background, self.foreground
+    )
+    .ok();
+    for (name, style) in self.styles {
+      if let Some(index) = HIGHLIGHT_NAMES.iter().position(|n| n == name) {
+        writeln!(w, ".code-block-inner .hh{index} {{ {style}; }}").ok();
+      }
+    }
+  }
+}
+
+/// Builds a stylesheet from a light theme and an optional dark theme, the latter
+/// applied through `prefers-color-scheme`
+pub fn stylesheet(theme: Option<&Theme>, dark_theme: Option<&Theme>) -> Option<String> {
+  if theme.is_none() && dark_theme.is_none() {
+    return None;
+  }
+
+  let mut css = String::new();
+  if let Some(theme) = theme {
+    theme.write_css(&mut css);
+  }
+  if let Some(dark_theme) = dark_theme {
+    css.push_str("@media (prefers-color-scheme: dark) {\n");
+    dark_theme.write_css(&mut css);
+    css.push_str("}\n");
+  }
+  Some(css)
+}
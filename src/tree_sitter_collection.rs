@@ -1,21 +1,21 @@
+use tree_sitter::QueryError;
 use tree_sitter_highlight::HighlightConfiguration;
 pub struct TreeSitterCollection {
   pub conf: HighlightConfiguration,
 }
 
 impl TreeSitterCollection {
-  pub fn rust() -> TreeSitterCollection {
+  pub fn rust() -> Result<TreeSitterCollection, QueryError> {
     let rust_conf = HighlightConfiguration::new(
       tree_sitter_rust::language(),
       tree_sitter_rust::HIGHLIGHT_QUERY,
       "",
       "",
-    )
-    .unwrap();
+    )?;
 
-    TreeSitterCollection { conf: rust_conf }
+    Ok(TreeSitterCollection { conf: rust_conf })
   }
-  pub fn typescript() -> TreeSitterCollection {
+  pub fn typescript() -> Result<TreeSitterCollection, QueryError> {
     let mut highlights = tree_sitter_typescript::HIGHLIGHT_QUERY.to_owned();
     highlights.push_str(tree_sitter_javascript::HIGHLIGHT_QUERY);
 
@@ -27,13 +27,12 @@ impl TreeSitterCollection {
       &highlights,
       tree_sitter_javascript::INJECTION_QUERY,
       &locals,
-    )
-    .unwrap();
+    )?;
 
-    TreeSitterCollection { conf }
+    Ok(TreeSitterCollection { conf })
   }
 
-  pub fn tsx() -> TreeSitterCollection {
+  pub fn tsx() -> Result<TreeSitterCollection, QueryError> {
     let mut highlights = tree_sitter_javascript::JSX_HIGHLIGHT_QUERY.to_owned();
     highlights.push_str(tree_sitter_typescript::HIGHLIGHT_QUERY);
     highlights.push_str(tree_sitter_javascript::HIGHLIGHT_QUERY);
@@ -46,102 +45,93 @@ impl TreeSitterCollection {
       &highlights,
       tree_sitter_javascript::INJECTION_QUERY,
       &locals,
-    )
-    .unwrap();
+    )?;
 
-    TreeSitterCollection { conf }
+    Ok(TreeSitterCollection { conf })
   }
-  pub fn javascript() -> TreeSitterCollection {
+  pub fn javascript() -> Result<TreeSitterCollection, QueryError> {
     let conf = HighlightConfiguration::new(
       tree_sitter_javascript::language(),
       tree_sitter_javascript::HIGHLIGHT_QUERY,
       tree_sitter_javascript::INJECTION_QUERY,
       tree_sitter_javascript::LOCALS_QUERY,
-    )
-    .unwrap();
+    )?;
 
-    TreeSitterCollection { conf }
+    Ok(TreeSitterCollection { conf })
   }
-  pub fn jsx() -> TreeSitterCollection {
+  pub fn jsx() -> Result<TreeSitterCollection, QueryError> {
     let conf = HighlightConfiguration::new(
       tree_sitter_javascript::language(),
       tree_sitter_javascript::JSX_HIGHLIGHT_QUERY,
       tree_sitter_javascript::INJECTION_QUERY,
       "",
-    )
-    .unwrap();
+    )?;
 
-    TreeSitterCollection { conf }
+    Ok(TreeSitterCollection { conf })
   }
-  pub fn go() -> TreeSitterCollection {
+  pub fn go() -> Result<TreeSitterCollection, QueryError> {
     let conf = HighlightConfiguration::new(
       tree_sitter_go::language(),
       tree_sitter_go::HIGHLIGHT_QUERY,
       "",
       "",
-    )
-    .unwrap();
+    )?;
 
-    TreeSitterCollection { conf }
+    Ok(TreeSitterCollection { conf })
   }
-  pub fn c() -> TreeSitterCollection {
+  pub fn c() -> Result<TreeSitterCollection, QueryError> {
     let conf = HighlightConfiguration::new(
       tree_sitter_c::language(),
       tree_sitter_c::HIGHLIGHT_QUERY,
       "",
       "",
-    )
-    .unwrap();
+    )?;
 
-    TreeSitterCollection { conf }
+    Ok(TreeSitterCollection { conf })
   }
-  pub fn html() -> TreeSitterCollection {
+  pub fn html() -> Result<TreeSitterCollection, QueryError> {
     let conf = HighlightConfiguration::new(
       tree_sitter_html::language(),
       tree_sitter_html::HIGHLIGHT_QUERY,
       tree_sitter_html::INJECTION_QUERY,
       "",
-    )
-    .unwrap();
+    )?;
 
-    TreeSitterCollection { conf }
+    Ok(TreeSitterCollection { conf })
   }
-  pub fn toml() -> TreeSitterCollection {
+  pub fn toml() -> Result<TreeSitterCollection, QueryError> {
     let conf = HighlightConfiguration::new(
       tree_sitter_toml::language(),
       tree_sitter_toml::HIGHLIGHT_QUERY,
       "",
       "",
-    )
-    .unwrap();
+    )?;
 
-    TreeSitterCollection { conf }
+    Ok(TreeSitterCollection { conf })
   }
-  pub fn python() -> TreeSitterCollection {
+  pub fn python() -> Result<TreeSitterCollection, QueryError> {
     let conf = HighlightConfiguration::new(
       tree_sitter_python::language(),
       tree_sitter_python::HIGHLIGHT_QUERY,
       "",
       "",
-    )
-    .unwrap();
+    )?;
 
-    TreeSitterCollection { conf }
+    Ok(TreeSitterCollection { conf })
   }
-  pub fn dockerfile() -> TreeSitterCollection {
-    let conf = HighlightConfiguration::new(tree_sitter_dockerfile::language(), "", "", "").unwrap();
+  pub fn dockerfile() -> Result<TreeSitterCollection, QueryError> {
+    let conf = HighlightConfiguration::new(tree_sitter_dockerfile::language(), "", "", "")?;
 
-    TreeSitterCollection { conf }
+    Ok(TreeSitterCollection { conf })
   }
-  pub fn json() -> TreeSitterCollection {
+  pub fn json() -> Result<TreeSitterCollection, QueryError> {
     let conf = HighlightConfiguration::new(
       tree_sitter_json::language(),
       tree_sitter_json::HIGHLIGHT_QUERY,
       "",
       "",
-    )
-    .unwrap();
+    )?;
 
-    TreeSitterCollection { conf }
+    Ok(TreeSitterCollection { conf })
   }
 }
@@ -0,0 +1,39 @@
+//! WebAssembly bindings for browsers and edge runtimes, exposed through wasm-bindgen.
+//! The tree-sitter grammars don't build for wasm32, so code blocks are escaped but not
+//! highlighted. Languages are still tagged, and detection falls back to its heuristics
+use crate::{render_markdown, MarkdownOptions};
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen(typescript_custom_section)]
+const TS_TYPES: &str = r#"
+export interface HtmlOutput {
+  toc?: string
+  content: string
+  warnings: Array<{ code: string, message: string }>
+  unresolvedLinks: Array<string>
+  outline: {
+    headings: Array<{ level: number, slug: string, text: string }>
+    links: Array<{ url: string, text: string }>
+    images: Array<{ src: string, alt: string, title?: string }>
+    footnotes: Array<{ label: string, references: number, defined: boolean }>
+    codeBlocks: Array<{ lang: string, lines: number }>
+  }
+  wordCount: number
+  readingTimeMinutes: number
+  excerpt: { text: string, html: string }
+  sections?: Array<{ slug: string, title: string, level: number, text: string }>
+}
+"#;
+
+/// Renders markdown to HTML, taking the same options as the Node addon's
+/// `processMarkdownToHtml`
+#[wasm_bindgen(js_name = processMarkdownToHtml, unchecked_return_type = "HtmlOutput")]
+pub fn process_markdown_to_html(input: &str, options: JsValue) -> Result<JsValue, JsError> {
+  let options: MarkdownOptions = if options.is_undefined() || options.is_null() {
+    MarkdownOptions::default()
+  } else {
+    serde_wasm_bindgen::from_value(options)?
+  };
+  let output = render_markdown(input, options)?;
+  Ok(serde_wasm_bindgen::to_value(&output)?)
+}
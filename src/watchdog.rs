@@ -0,0 +1,105 @@
+use once_cell::sync::Lazy;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+static WATCHDOG: Lazy<Watchdog> = Lazy::new(Watchdog::start);
+
+/// Raises tree-sitter cancellation flags once their deadline passes. A single thread serves
+/// every highlighter, so a time budget doesn't cost a thread per code block
+struct Watchdog {
+  deadlines: Mutex<BinaryHeap<Reverse<Deadline>>>,
+  changed: Condvar,
+}
+
+struct Deadline {
+  at: Instant,
+  flag: Weak<AtomicUsize>,
+}
+
+impl PartialEq for Deadline {
+  fn eq(&self, other: &Self) -> bool {
+    self.at == other.at
+  }
+}
+
+impl Eq for Deadline {}
+
+impl PartialOrd for Deadline {
+  fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Ord for Deadline {
+  fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+    self.at.cmp(&other.at)
+  }
+}
+
+impl Watchdog {
+  fn start() -> Self {
+    thread::Builder::new()
+      .name("femark-watchdog".into())
+      .spawn(|| WATCHDOG.run())
+      .expect("could not spawn the highlight watchdog thread");
+    Self {
+      deadlines: Default::default(),
+      changed: Condvar::new(),
+    }
+  }
+
+  fn run(&self) {
+    let mut deadlines = self.deadlines.lock().unwrap();
+    loop {
+      let now = Instant::now();
+      while let Some(Reverse(next)) = deadlines.peek() {
+        if next.at > now {
+          break;
+        }
+        // Blocks that finished in time dropped their flag already
+        if let Some(flag) = next.flag.upgrade() {
+          flag.store(1, Ordering::Relaxed);
+        }
+        deadlines.pop();
+      }
+
+      deadlines = match deadlines.peek() {
+        Some(Reverse(next)) => {
+          let timeout = next.at.saturating_duration_since(now);
+          self.changed.wait_timeout(deadlines, timeout).unwrap().0
+        }
+        None => self.changed.wait(deadlines).unwrap(),
+      };
+    }
+  }
+}
+
+/// Returns a cancellation flag that is raised once `budget` has elapsed
+pub(crate) fn cancel_after(budget: Duration) -> Arc<AtomicUsize> {
+  let flag = Arc::new(AtomicUsize::new(0));
+  let watchdog = &*WATCHDOG;
+  watchdog.deadlines.lock().unwrap().push(Reverse(Deadline {
+    at: Instant::now() + budget,
+    flag: Arc::downgrade(&flag),
+  }));
+  watchdog.changed.notify_one();
+  flag
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_flags_are_raised_after_their_budget() {
+    let late = cancel_after(Duration::from_millis(10));
+    let never = cancel_after(Duration::from_secs(3600));
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(late.load(Ordering::Relaxed), 1);
+    assert_eq!(never.load(Ordering::Relaxed), 0);
+  }
+}